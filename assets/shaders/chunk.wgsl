@@ -1,96 +1,277 @@
+// the same vertex shader runs in the main pass and in the prepass, which also draws the shadow
+// maps. the prepass only needs the fragment shader for cutout textures, clamping depth in
+// directional shadow maps, and writing the gbuffer in deferred mode
+#ifdef PREPASS_PIPELINE
+#ifdef PREPASS_FRAGMENT
+#import bevy_pbr::prepass_io::FragmentOutput
+#endif
+#ifdef DEFERRED_PREPASS
 #import bevy_pbr::{
+    prepass_io::VertexOutput,
     pbr_fragment::pbr_input_from_standard_material,
     pbr_functions::alpha_discard,
-}
-
-#ifdef PREPASS_PIPELINE
-#import bevy_pbr::{
-    prepass_io::{VertexOutput, FragmentOutput},
     pbr_deferred_functions::deferred_output,
 }
 #else
+#import bevy_pbr::pbr_bindings
+#endif
+#else
 #import bevy_pbr::{
     forward_io::{VertexOutput, FragmentOutput},
-    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
 }
 #endif
 
 #import bevy_pbr::mesh_functions
+#import bevy_render::instance_index::get_instance_index
 
 @group(1) @binding(100) var array_texture: texture_2d_array<f32>;
 @group(1) @binding(101) var texture_sampler: sampler;
+@group(1) @binding(102) var<uniform> daylight: f32;
+@group(1) @binding(103) var<uniform> origin: vec3<i32>;
 
 struct CustomVertex {
     @builtin(instance_index) instance_index: u32,
-    @location(0) position: vec3<f32>,
-    @location(1) normal: vec3<f32>,
-    @location(2) uv: vec2<f32>,
-    @location(3) index: u32,
+    @location(0) packed: vec2<u32>,
 }
 
 struct CustomVertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) world_position: vec4<f32>,
     @location(1) world_normal: vec3<f32>,
-    @location(2) uv: vec2<f32>,
-    @location(3) @interpolate(flat) index: u32,
+    @location(2) local_position: vec3<f32>,
+    @location(3) @interpolate(flat) surface: u32,
+    @location(4) @interpolate(flat) texture: u32,
+    @location(5) occlusion: f32,
+    @location(6) @interpolate(flat) light: vec4<f32>,
+    @location(7) @interpolate(flat) instance_index: u32,
+#ifdef DEPTH_CLAMP_ORTHO
+    @location(8) clip_position_unclamped: vec4<f32>,
+#endif
+}
+
+// surfaces 0 to 5 are the block faces, 6 is the diagonal quads of plants which are lit from above
+fn surface_normal(surface: u32) -> vec3<f32> {
+    switch surface {
+        case 0u: { return vec3<f32>(-1.0, 0.0, 0.0); }
+        case 1u: { return vec3<f32>(1.0, 0.0, 0.0); }
+        case 3u: { return vec3<f32>(0.0, -1.0, 0.0); }
+        case 4u: { return vec3<f32>(0.0, 0.0, 1.0); }
+        case 5u: { return vec3<f32>(0.0, 0.0, -1.0); }
+        default: { return vec3<f32>(0.0, 1.0, 0.0); }
+    }
+}
+
+// every level of light below the brightest is 80% as bright as the one above it
+fn light_brightness(level: vec3<f32>) -> vec3<f32> {
+    return pow(vec3<f32>(0.8), 15.0 - level);
+}
+
+// sunlight in x, scaled by how bright the sky is at this time of day
+fn sunlight(light: vec4<f32>) -> vec3<f32> {
+    return light_brightness(vec3<f32>(light.x)) * daylight;
+}
+
+// block light in yzw, where each color channel only brightens the surface past what the sun
+// already lights it to. it's emitted rather than lit, so it doesn't fade with the sky at night
+fn block_light(light: vec4<f32>) -> vec3<f32> {
+    let block = light_brightness(light.yzw) * step(vec3<f32>(0.5), light.yzw);
+    return max(block - sunlight(light), vec3<f32>(0.0));
+}
+
+// projects the texture onto the surface from the block grid, repeating it once per block
+fn surface_uv(local_position: vec3<f32>, surface: u32, quarter_turns: u32) -> vec2<f32> {
+    let p = fract(local_position);
+
+    var uv: vec2<f32>;
+    switch surface {
+        case 0u, 1u: { uv = vec2<f32>(p.z, 1.0 - p.y); }
+        case 2u, 3u: { uv = vec2<f32>(p.z, p.x); }
+        default: { uv = vec2<f32>(p.x, 1.0 - p.y); }
+    }
+
+    for (var i = 0u; i < quarter_turns; i++) {
+        uv = vec2<f32>(1.0 - uv.y, uv.x);
+    }
+
+    return uv;
+}
+
+// mixes the position of a block and a surface of it into a hash, the same way as
+// `texture_hash` in block_definitions.rs
+fn texture_hash(block: vec3<i32>, surface: u32) -> u32 {
+    let p = bitcast<vec3<u32>>(block);
+    var hash = p.x * 0x8da6b343u ^ p.y * 0xd8163841u ^ p.z * 0xcb1ab31fu ^ (surface + 1u) * 0x9e3779b9u;
+    hash = (hash ^ (hash >> 16u)) * 0x7feb352du;
+    hash = (hash ^ (hash >> 15u)) * 0x846ca68bu;
+    return hash ^ (hash >> 16u);
+}
+
+// the texture holds its first layer, how many variants follow it and whether it can be turned.
+// those are picked per block here rather than per face when meshing, so that merged quads still
+// vary from block to block
+fn texture_layer(mesh: CustomVertexOutput) -> vec2<u32> {
+    // faces lie on the side of their block, so step back inside it. plants are in the middle
+    var inward = vec3<f32>(0.0);
+    if mesh.surface < 6u {
+        inward = surface_normal(mesh.surface) / 32.0;
+    }
+    let local_block = vec3<i32>(floor(mesh.local_position - inward));
+
+    // chunks sit at the origin of their grid cell, which is a chunk wide
+    let chunk = vec3<i32>(round((mesh.world_position.xyz - mesh.local_position) / 32.0)) + origin;
+    let hash = texture_hash(chunk * 32 + local_block, mesh.surface);
+
+    let rotate = mesh.texture & 1u;
+    let variants = ((mesh.texture >> 1u) & 7u) + 1u;
+    let layer = (mesh.texture >> 4u) + hash % variants;
+    let quarter_turns = ((hash >> 8u) & 3u) * rotate;
+    return vec2<u32>(layer, quarter_turns);
 }
 
 @vertex
 fn vertex(vertex: CustomVertex) -> CustomVertexOutput {
+    let position = vec3<f32>(
+        f32(vertex.packed.x & 0x3ffu),
+        f32((vertex.packed.x >> 10u) & 0x3ffu),
+        f32((vertex.packed.x >> 20u) & 0x3ffu),
+    ) / 16.0;
+    let surface = vertex.packed.y & 7u;
+
     var out: CustomVertexOutput;
     var model = mesh_functions::get_model_matrix(vertex.instance_index);
     out.clip_position = mesh_functions::mesh_position_local_to_clip(
         model,
-        vec4<f32>(vertex.position, 1.0),
+        vec4<f32>(position, 1.0),
     );
+#ifdef DEPTH_CLAMP_ORTHO
+    // geometry behind the near plane of a shadow cascade still casts shadows into it
+    out.clip_position_unclamped = out.clip_position;
+    out.clip_position.z = min(out.clip_position.z, 1.0);
+#endif
     out.world_position = mesh_functions::mesh_position_local_to_world(
         model,
-        vec4<f32>(vertex.position, 1.0),
+        vec4<f32>(position, 1.0),
     );
     out.world_normal = mesh_functions::mesh_normal_local_to_world(
-        vertex.normal,
+        surface_normal(surface),
         vertex.instance_index,
     );
-    out.uv = vertex.uv;
-    out.index = vertex.index;
+    out.local_position = position;
+    out.surface = surface;
+    out.texture = (vertex.packed.y >> 3u) & 0x1fffu;
+    out.occlusion = f32(vertex.packed.x >> 30u) / 3.0;
+    out.light = vec4<f32>(
+        f32((vertex.packed.y >> 16u) & 0xfu),
+        f32((vertex.packed.y >> 20u) & 0xfu),
+        f32((vertex.packed.y >> 24u) & 0xfu),
+        f32((vertex.packed.y >> 28u) & 0xfu),
+    );
+    out.instance_index = get_instance_index(vertex.instance_index);
     return out;
 }
 
+struct ChunkColor {
+    base: vec4<f32>,
+    emissive: vec4<f32>,
+}
+
+fn chunk_color(mesh: CustomVertexOutput) -> ChunkColor {
+    let texture = texture_layer(mesh);
+    let uv = surface_uv(mesh.local_position, mesh.surface, texture.y);
+    let color = textureSample(array_texture, texture_sampler, uv, texture.x);
+
+    // darken corners enclosed by neighboring blocks and places the sky doesn't reach, and light
+    // up the ones around light sources
+    let albedo = color.rgb * mix(0.4, 1.0, mesh.occlusion);
+
+    var out: ChunkColor;
+    out.base = vec4<f32>(albedo * sunlight(mesh.light), color.a);
+    out.emissive = vec4<f32>(albedo * block_light(mesh.light), 1.0);
+    return out;
+}
+
+#ifdef PREPASS_PIPELINE
+#ifdef DEFERRED_PREPASS
 @fragment
 fn fragment(
     @builtin(front_facing) is_front: bool,
     mesh: CustomVertexOutput,
 ) -> FragmentOutput {
-    let color = textureSample(array_texture, texture_sampler, mesh.uv, mesh.index);
+    let color = chunk_color(mesh);
 
     var in: VertexOutput;
     in.position = mesh.clip_position;
     in.world_position = mesh.world_position;
     in.world_normal = mesh.world_normal;
-    in.uv = mesh.uv;
+    in.instance_index = mesh.instance_index;
 
-    // generate a PbrInput struct from the StandardMaterial bindings
     var pbr_input = pbr_input_from_standard_material(in, is_front);
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, color.base);
+    pbr_input.material.emissive = color.emissive;
 
-    // we can optionally modify the input before lighting and alpha_discard is applied
-    pbr_input.material.base_color = color;
+    // in deferred mode we can't modify anything after that, as lighting is run in a separate fullscreen shader.
+    return deferred_output(in, pbr_input);
+}
+#else
+// discards the transparent texels of cutout blocks, so their holes let light through
+fn prepass_alpha_discard(mesh: CustomVertexOutput) {
+#ifdef MAY_DISCARD
+    if chunk_color(mesh).base.a < pbr_bindings::material.alpha_cutoff {
+        discard;
+    }
+#endif
+}
 
-    // alpha discard
-    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+#ifdef PREPASS_FRAGMENT
+@fragment
+fn fragment(mesh: CustomVertexOutput) -> FragmentOutput {
+    prepass_alpha_discard(mesh);
 
-#ifdef PREPASS_PIPELINE
-    // in deferred mode we can't modify anything after that, as lighting is run in a separate fullscreen shader.
-    let out = deferred_output(in, pbr_input);
+    var out: FragmentOutput;
+#ifdef NORMAL_PREPASS
+    out.normal = vec4<f32>(mesh.world_normal * 0.5 + vec3<f32>(0.5), 1.0);
+#endif
+#ifdef DEPTH_CLAMP_ORTHO
+    out.frag_depth = mesh.clip_position_unclamped.z;
+#endif
+    return out;
+}
+#else
+@fragment
+fn fragment(mesh: CustomVertexOutput) {
+    prepass_alpha_discard(mesh);
+}
+#endif
+#endif
 #else
+@fragment
+fn fragment(
+    @builtin(front_facing) is_front: bool,
+    mesh: CustomVertexOutput,
+) -> FragmentOutput {
+    let color = chunk_color(mesh);
+
+    var in: VertexOutput;
+    in.position = mesh.clip_position;
+    in.world_position = mesh.world_position;
+    in.world_normal = mesh.world_normal;
+    in.instance_index = mesh.instance_index;
+
+    // generate a PbrInput struct from the StandardMaterial bindings, with the color of the chunk
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, color.base);
+    pbr_input.material.emissive = color.emissive;
+
     var out: FragmentOutput;
-    // apply lighting
+    // apply lighting, including the shadows of the sun
     out.color = apply_pbr_lighting(pbr_input);
 
     // apply in-shader post processing (fog, alpha-premultiply, and also tonemapping, debanding if the camera is non-hdr)
     // note this does not include fullscreen postprocessing effects like bloom.
     out.color = main_pass_post_lighting_processing(pbr_input, out.color);
-#endif
 
     return out;
 }
+#endif
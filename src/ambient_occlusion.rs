@@ -0,0 +1,137 @@
+use bevy::prelude::*;
+use itertools::Itertools;
+
+use crate::{
+    block::BlockFace,
+    block_definitions::BlockModels,
+    voxel::{
+        chunk::{Chunk, CHUNK_SIZE},
+        chunk_index::ChunkIndex,
+        chunk_pos::ChunkPos,
+    },
+};
+
+const PADDED_SIZE: usize = CHUNK_SIZE + 2;
+
+/// Ambient occlusion at the corners of a face, from 0 for fully occluded to 3 for open.
+///
+/// Corners are ordered (0, 0), (1, 0), (1, 1), (0, 1) along the two axes of the face, which are
+/// (z, y) for left and right faces, (x, z) for top and bottom faces and (x, y) for front and back
+/// faces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaceOcclusion(pub [u8; 4]);
+
+impl FaceOcclusion {
+    /// The value of a corner with nothing around it.
+    pub const OPEN: u8 = 3;
+    pub const NONE: Self = Self([Self::OPEN; 4]);
+
+    /// Whether the face should be split along the (1, 0) to (0, 1) diagonal instead of the default
+    /// one. Splitting along the brighter diagonal keeps a single dark corner from bleeding across
+    /// the whole quad.
+    pub fn flipped(self) -> bool {
+        self.0[0] + self.0[2] < self.0[1] + self.0[3]
+    }
+
+    /// Whether the brightness changes going along the first axis of the face, between corners 0
+    /// and 1 or corners 3 and 2.
+    pub fn varies_along_u(self) -> bool {
+        self.0[0] != self.0[1] || self.0[3] != self.0[2]
+    }
+
+    /// Whether the brightness changes going along the second axis of the face, between corners 0
+    /// and 3 or corners 1 and 2.
+    pub fn varies_along_v(self) -> bool {
+        self.0[0] != self.0[3] || self.0[1] != self.0[2]
+    }
+
+    pub fn to_bits(self) -> u32 {
+        self.0
+            .iter()
+            .fold(0, |bits, &corner| bits << 2 | corner as u32)
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        Self([0, 1, 2, 3].map(|corner| (bits >> (6 - corner * 2) & 3) as u8))
+    }
+}
+
+/// Which blocks in and around a chunk darken the corners of the faces next to them.
+///
+/// The chunk is padded by one block on every side, taken from the surrounding chunks, so that
+/// faces on the chunk boundary are shaded the same as the ones inside it.
+pub struct Occluders(Vec<bool>);
+
+impl Occluders {
+    /// Takes the chunk and its neighbors in the order of [`ChunkPos::neighborhood`].
+    pub fn new(neighborhood: &[Option<Chunk>; 27], models: &BlockModels) -> Self {
+        let mut solid = vec![false; PADDED_SIZE * PADDED_SIZE * PADDED_SIZE];
+
+        let range = |offset: i32| match offset {
+            -1 => CHUNK_SIZE - 1..CHUNK_SIZE,
+            0 => 0..CHUNK_SIZE,
+            _ => 0..1,
+        };
+
+        let padded = |local: usize, offset: i32| local as i32 + offset * CHUNK_SIZE as i32 + 1;
+
+        for (offset, chunk) in ChunkPos::ZERO.neighborhood().iter().zip(neighborhood) {
+            let Some(chunk) = chunk else {
+                continue;
+            };
+
+            let chunk = chunk.read();
+
+            for ((x, y), z) in range(offset.x)
+                .cartesian_product(range(offset.y))
+                .cartesian_product(range(offset.z))
+            {
+                let position = IVec3::new(
+                    padded(x, offset.x),
+                    padded(y, offset.y),
+                    padded(z, offset.z),
+                );
+
+                solid[Self::index(position)] = chunk
+                    .block(ChunkIndex::new(x, y, z))
+                    .is_some_and(|block| block.casts_occlusion(models));
+            }
+        }
+
+        Self(solid)
+    }
+
+    /// The occlusion of a face of the block at the given position in the chunk, from the three
+    /// blocks in front of the face that touch each of its corners.
+    pub fn face(&self, x: usize, y: usize, z: usize, face: BlockFace) -> FaceOcclusion {
+        let (normal, u, v) = match face {
+            BlockFace::Left => (IVec3::NEG_X, IVec3::Z, IVec3::Y),
+            BlockFace::Right => (IVec3::X, IVec3::Z, IVec3::Y),
+            BlockFace::Top => (IVec3::Y, IVec3::X, IVec3::Z),
+            BlockFace::Bottom => (IVec3::NEG_Y, IVec3::X, IVec3::Z),
+            BlockFace::Front => (IVec3::Z, IVec3::X, IVec3::Y),
+            BlockFace::Back => (IVec3::NEG_Z, IVec3::X, IVec3::Y),
+        };
+
+        let front = IVec3::new(x as i32, y as i32, z as i32) + 1 + normal;
+        let solid = |position: IVec3| self.0[Self::index(position)] as u8;
+
+        FaceOcclusion([(0, 0), (1, 0), (1, 1), (0, 1)].map(|(cu, cv)| {
+            let u = if cu == 0 { -u } else { u };
+            let v = if cv == 0 { -v } else { v };
+
+            let (side_u, side_v) = (solid(front + u), solid(front + v));
+
+            if side_u + side_v == 2 {
+                0
+            } else {
+                3 - side_u - side_v - solid(front + u + v)
+            }
+        }))
+    }
+
+    fn index(position: IVec3) -> usize {
+        (position.x as usize * PADDED_SIZE + position.y as usize) * PADDED_SIZE
+            + position.z as usize
+    }
+}
@@ -0,0 +1,9 @@
+//! Exports a region of a world without starting the game, as described in [`game::export`].
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(error) = game::export::run(&args) {
+        eprintln!("{error}");
+        std::process::exit(1);
+    }
+}
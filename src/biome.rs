@@ -0,0 +1,216 @@
+use splines::{Interpolation, Key, Spline};
+
+use crate::{
+    block::Block,
+    feature::{Feature, FeatureRule},
+};
+
+/// How sharply biomes give way to each other. Higher values narrow the borders where their
+/// heights are blended.
+const BLEND_SHARPNESS: f64 = 24.0;
+
+/// Biomes with less weight than this are left out of the blend.
+const MIN_WEIGHT: f64 = 0.001;
+
+/// The climate noises sampled for a column, each roughly between -1 and 1.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Climate {
+    pub temperature: f64,
+    pub humidity: f64,
+    /// How far inland the column is, low near the coast and high deep inside continents.
+    pub continentalness: f64,
+    /// How worn down the terrain is, low for jagged mountains and high for flat lands.
+    pub erosion: f64,
+}
+
+impl Climate {
+    fn distance_squared(self, other: Self) -> f64 {
+        (self.temperature - other.temperature).powi(2)
+            + (self.humidity - other.humidity).powi(2)
+            + (self.continentalness - other.continentalness).powi(2)
+            + (self.erosion - other.erosion).powi(2)
+    }
+}
+
+/// A block placed on top of the surface, such as a plant.
+#[derive(Debug, Clone, Copy)]
+pub struct Decoration {
+    pub block: Block,
+    /// The chance of the decoration growing on any surface block, between 0 and 1.
+    pub chance: f64,
+}
+
+pub struct Biome {
+    pub name: &'static str,
+    /// The climate the biome is found in. Every column belongs to the biome closest to its climate.
+    pub climate: Climate,
+    /// The top block of the ground.
+    pub surface: Block,
+    /// The blocks between the surface and the rock below it.
+    pub subsurface: Block,
+    /// The height of the terrain by continentalness.
+    pub height: Spline<f64, f64>,
+    pub decorations: Vec<Decoration>,
+    /// Trees, boulders and other things larger than a block.
+    pub features: Vec<FeatureRule>,
+}
+
+impl Biome {
+    pub fn height(&self, continentalness: f64) -> f64 {
+        self.height
+            .clamped_sample(continentalness)
+            .unwrap_or_default()
+    }
+}
+
+/// Every biome the level generator can pick from.
+pub struct BiomeRegistry {
+    biomes: Vec<Biome>,
+}
+
+impl Default for BiomeRegistry {
+    fn default() -> Self {
+        let height = |keys: &[(f64, f64)]| {
+            Spline::from_vec(
+                keys.iter()
+                    .map(|&(t, height)| Key::new(t, height, Interpolation::Linear))
+                    .collect(),
+            )
+        };
+        let climate = |temperature, humidity, continentalness, erosion| Climate {
+            temperature,
+            humidity,
+            continentalness,
+            erosion,
+        };
+        let tall_grass = |chance| Decoration {
+            block: Block::TallGrass,
+            chance,
+        };
+        let tree = |chance| FeatureRule {
+            feature: Feature::Tree {
+                min_height: 4,
+                max_height: 7,
+            },
+            chance,
+        };
+        let boulder = |radius, chance| FeatureRule {
+            feature: Feature::Boulder { radius },
+            chance,
+        };
+
+        Self::new(vec![
+            Biome {
+                name: "Plains",
+                climate: climate(0.0, 0.0, 0.0, 0.0),
+                surface: Block::Grass,
+                subsurface: Block::Dirt,
+                height: height(&[(-1.0, 50.0), (0.3, 100.0), (1.0, 150.0)]),
+                decorations: vec![tall_grass(0.1)],
+                features: vec![tree(0.15), boulder(2.0, 0.02)],
+            },
+            Biome {
+                name: "Meadow",
+                climate: climate(0.1, 0.6, 0.0, 0.2),
+                surface: Block::Grass,
+                subsurface: Block::Dirt,
+                height: height(&[(-1.0, 55.0), (0.3, 95.0), (1.0, 130.0)]),
+                decorations: vec![tall_grass(0.35)],
+                features: vec![tree(0.04)],
+            },
+            Biome {
+                name: "Desert",
+                climate: climate(0.6, -0.5, 0.0, 0.2),
+                surface: Block::Sand,
+                subsurface: Block::Sand,
+                height: height(&[(-1.0, 55.0), (0.3, 90.0), (1.0, 120.0)]),
+                decorations: vec![],
+                features: vec![],
+            },
+            Biome {
+                name: "Beach",
+                climate: climate(0.2, 0.0, -0.35, 0.3),
+                surface: Block::Sand,
+                subsurface: Block::Sand,
+                height: height(&[(-1.0, 50.0), (-0.3, 65.0), (0.3, 72.0), (1.0, 80.0)]),
+                decorations: vec![],
+                features: vec![],
+            },
+            Biome {
+                name: "Ocean",
+                climate: climate(0.0, 0.0, -0.7, 0.0),
+                surface: Block::Sand,
+                subsurface: Block::Sand,
+                height: height(&[(-1.0, 30.0), (-0.4, 52.0), (0.0, 62.0), (1.0, 70.0)]),
+                decorations: vec![],
+                features: vec![],
+            },
+            Biome {
+                name: "Tundra",
+                climate: climate(-0.6, 0.0, 0.0, 0.0),
+                surface: Block::Ice,
+                subsurface: Block::Dirt,
+                height: height(&[(-1.0, 50.0), (0.3, 95.0), (1.0, 140.0)]),
+                decorations: vec![],
+                features: vec![boulder(2.5, 0.05)],
+            },
+            Biome {
+                name: "Mountains",
+                climate: climate(-0.1, 0.0, 0.5, -0.6),
+                surface: Block::Rock,
+                subsurface: Block::Rock,
+                height: height(&[(-1.0, 60.0), (0.0, 120.0), (0.5, 190.0), (1.0, 240.0)]),
+                decorations: vec![],
+                features: vec![boulder(3.5, 0.1)],
+            },
+        ])
+    }
+}
+
+impl BiomeRegistry {
+    pub fn new(biomes: Vec<Biome>) -> Self {
+        assert!(
+            !biomes.is_empty(),
+            "a biome registry needs at least one biome"
+        );
+        Self { biomes }
+    }
+
+    pub fn biomes(&self) -> &[Biome] {
+        &self.biomes
+    }
+
+    /// The biome whose climate is closest.
+    pub fn biome(&self, climate: Climate) -> &Biome {
+        self.biomes
+            .iter()
+            .min_by(|a, b| {
+                let a = a.climate.distance_squared(climate);
+                let b = b.climate.distance_squared(climate);
+                a.total_cmp(&b)
+            })
+            .unwrap()
+    }
+
+    /// The terrain height of a column, blended between the biomes that are close to its climate so
+    /// there are no cliffs at biome borders.
+    pub fn height(&self, climate: Climate) -> f64 {
+        let closest = self.biome(climate).climate.distance_squared(climate);
+
+        let (total_weight, total_height) = self
+            .biomes
+            .iter()
+            .filter_map(|biome| {
+                // Relative to the closest biome, so far away climates don't underflow everything.
+                let distance = biome.climate.distance_squared(climate) - closest;
+                let weight = (-distance * BLEND_SHARPNESS).exp();
+                (weight >= MIN_WEIGHT)
+                    .then(|| (weight, weight * biome.height(climate.continentalness)))
+            })
+            .fold((0.0, 0.0), |(w, h), (weight, height)| {
+                (w + weight, h + height)
+            });
+
+        total_height / total_weight
+    }
+}
@@ -3,41 +3,282 @@ use bevy::{
     prelude::*,
 };
 use num_derive::{FromPrimitive, ToPrimitive};
+use serde::{Deserialize, Serialize};
 
-use crate::mesh_builder::MeshBuilder;
+use crate::{
+    ambient_occlusion::FaceOcclusion,
+    block_definitions::{BlockModels, BlockTextures, FaceTexture},
+    block_model::{BlockModel, ModelBox, CUBE},
+    light::Light,
+    mesh_builder::{MeshBuilder, Surface},
+};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, ToPrimitive, FromPrimitive)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, ToPrimitive, FromPrimitive, Serialize, Deserialize,
+)]
 #[repr(u8)]
 pub enum Block {
     Dirt,
     Grass,
     Rock,
     Sand,
+    RockSlab,
+    RockStairs,
+    Fence,
+    TallGrass,
+    Glass,
+    Water,
+    Ice,
+    Log,
+    Furnace,
+    Pillar,
+    Lamp,
+    Magma,
+    Crystal,
+    Slate,
+    CoalOre,
+    IronOre,
+    GoldOre,
+    Leaves,
+    Gravel,
+}
+
+/// How a block's facing affects the way it's rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientable {
+    Fixed,
+    /// The top of the block points along the facing, like logs and pillars.
+    Axis,
+    /// The front of the block points along the facing, turned around the vertical axis.
+    Horizontal,
+}
+
+/// The render pass a block's faces are drawn in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockLayer {
+    Opaque,
+    /// Fully transparent texels are discarded, everything else is opaque.
+    Cutout,
+    /// Alpha blended, drawn after everything else.
+    Translucent,
 }
 
 impl Block {
-    fn face_index(self, face: BlockFace) -> u32 {
+    pub fn orientable(self) -> Orientable {
+        match self {
+            Self::Log | Self::Pillar => Orientable::Axis,
+            Self::Furnace | Self::RockStairs => Orientable::Horizontal,
+            _ => Orientable::Fixed,
+        }
+    }
+
+    /// Picks the facing of a newly placed block from the face it was placed against and the
+    /// direction the player is looking in.
+    pub fn placement_facing(self, clicked: BlockFace, look: Vec3) -> BlockFace {
+        match self.orientable() {
+            Orientable::Fixed => BlockFace::default(),
+            Orientable::Axis => clicked,
+            Orientable::Horizontal => BlockFace::from_direction(-Vec3::new(look.x, 0.0, look.z)),
+        }
+    }
+
+    /// Maps a face in world space to the face of the unrotated block that's shown there.
+    pub fn local_face(self, face: BlockFace, facing: BlockFace) -> BlockFace {
+        match self.orientable() {
+            Orientable::Fixed => face,
+            Orientable::Axis if face == facing => BlockFace::Top,
+            Orientable::Axis if face == facing.opposite() => BlockFace::Bottom,
+            Orientable::Axis if facing.is_horizontal() => BlockFace::Front,
+            Orientable::Axis => face,
+            Orientable::Horizontal => face.turned(4 - facing.quarter_turns()),
+        }
+    }
+
+    /// The shape of the block, as given by the block definitions.
+    pub fn model(self, models: &BlockModels) -> &BlockModel {
+        models.get(self)
+    }
+
+    /// The boxes making up the model of the block, turned the way it's facing. Connections are
+    /// added towards the sides for which `connected` is true.
+    pub fn boxes<'a>(
+        self,
+        models: &'a BlockModels,
+        facing: BlockFace,
+        connected: impl Fn(BlockFace) -> bool + 'a,
+    ) -> impl Iterator<Item = ModelBox> + 'a {
+        let (boxes, connections): (&[_], &[_]) = match self.model(models) {
+            BlockModel::Boxes {
+                boxes, connections, ..
+            } => (&boxes[..], &connections[..]),
+            BlockModel::Cross => (&[], &[]),
+        };
+
+        let quarter_turns = match self.orientable() {
+            Orientable::Horizontal => facing.quarter_turns(),
+            _ => 0,
+        };
+
+        let connections = connections
+            .iter()
+            .filter(move |(face, _)| connected(face.turned(quarter_turns)))
+            .map(|(_, model_box)| model_box);
+
+        boxes
+            .iter()
+            .chain(connections)
+            .map(move |model_box| model_box.turned(quarter_turns))
+    }
+
+    /// Whether the block reaches out to a neighbor on the given side, which it does for blocks of
+    /// the same type and for opaque blocks with a full face towards it.
+    pub fn connects_to(
+        self,
+        models: &BlockModels,
+        face: BlockFace,
+        neighbor: Option<Block>,
+    ) -> bool {
+        neighbor.is_some_and(|neighbor| {
+            neighbor == self
+                || (neighbor.layer() == BlockLayer::Opaque
+                    && neighbor.model(models).occludes(face.opposite()))
+        })
+    }
+
+    pub fn layer(self) -> BlockLayer {
+        match self {
+            Self::TallGrass | Self::Glass | Self::Leaves => BlockLayer::Cutout,
+            Self::Water | Self::Ice => BlockLayer::Translucent,
+            _ => BlockLayer::Opaque,
+        }
+    }
+
+    pub fn has_collision(self, models: &BlockModels) -> bool {
+        self != Self::Water && self.model(models).has_collision()
+    }
+
+    /// The levels of red, green and blue light the block gives off, all zero if it doesn't.
+    pub fn light_color(self) -> [u8; 3] {
         match self {
-            Self::Dirt => 0,
-            Self::Grass if face == BlockFace::Top => 2,
-            Self::Grass if face == BlockFace::Bottom => 0,
-            Self::Grass => 1,
-            Self::Rock => 3,
-            Self::Sand => 4,
+            Self::Lamp => [15, 13, 9],
+            Self::Magma => [15, 7, 2],
+            Self::Crystal => [6, 10, 15],
+            _ => [0; 3],
         }
     }
+
+    /// Whether the block darkens the corners of faces next to it, which also keeps light from
+    /// passing through it.
+    pub fn casts_occlusion(self, models: &BlockModels) -> bool {
+        self.layer() == BlockLayer::Opaque && self.model(models) == &CUBE
+    }
+
+    /// Whether the given face of this block can be seen past the neighbor on that side.
+    ///
+    /// Neighbors that aren't opaque only hide faces of the same block type, so that the inside
+    /// of a body of glass or water isn't drawn while stone behind glass still is.
+    pub fn is_face_visible(
+        self,
+        models: &BlockModels,
+        face: BlockFace,
+        neighbor: Option<Block>,
+    ) -> bool {
+        neighbor.map_or(true, |neighbor| {
+            if !neighbor.model(models).occludes(face.opposite()) {
+                true
+            } else if neighbor.layer() == BlockLayer::Opaque {
+                false
+            } else {
+                neighbor != self
+            }
+        })
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum BlockFace {
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, ToPrimitive, FromPrimitive, Deserialize,
+)]
+#[repr(u8)]
+pub enum BlockFace {
     Left,
     Right,
+    #[default]
     Top,
     Bottom,
     Front,
     Back,
 }
 
+impl BlockFace {
+    pub const ALL: [Self; 6] = [
+        Self::Left,
+        Self::Right,
+        Self::Top,
+        Self::Bottom,
+        Self::Front,
+        Self::Back,
+    ];
+
+    /// The horizontal faces, in the order reached by turning around the vertical axis.
+    const HORIZONTAL: [Self; 4] = [Self::Front, Self::Right, Self::Back, Self::Left];
+
+    /// The face pointing closest to the given direction.
+    pub fn from_direction(direction: Vec3) -> Self {
+        let abs = direction.abs();
+
+        if abs.x >= abs.y && abs.x >= abs.z {
+            if direction.x < 0.0 {
+                Self::Left
+            } else {
+                Self::Right
+            }
+        } else if abs.y >= abs.z {
+            if direction.y < 0.0 {
+                Self::Bottom
+            } else {
+                Self::Top
+            }
+        } else if direction.z < 0.0 {
+            Self::Back
+        } else {
+            Self::Front
+        }
+    }
+
+    pub fn is_horizontal(self) -> bool {
+        Self::HORIZONTAL.contains(&self)
+    }
+
+    /// The number of quarter turns from the front to this face, or zero if it isn't horizontal.
+    pub fn quarter_turns(self) -> usize {
+        Self::HORIZONTAL
+            .iter()
+            .position(|&face| face == self)
+            .unwrap_or_default()
+    }
+
+    /// Turns a horizontal face by a number of quarter turns, leaving the top and bottom as is.
+    pub fn turned(self, quarter_turns: usize) -> Self {
+        if self.is_horizontal() {
+            Self::HORIZONTAL[(self.quarter_turns() + quarter_turns) % 4]
+        } else {
+            self
+        }
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+            Self::Top => Self::Bottom,
+            Self::Bottom => Self::Top,
+            Self::Front => Self::Back,
+            Self::Back => Self::Front,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct BlockFaces {
     pub left: bool,
     pub right: bool,
@@ -47,123 +288,224 @@ pub struct BlockFaces {
     pub back: bool,
 }
 
-pub fn render_cube(block: Block, chunk: &mut MeshBuilder, position: Vec3, faces: BlockFaces) {
-    let x = position.x;
-    let y = position.y;
-    let z = position.z;
+impl BlockFaces {
+    pub fn contains(&self, face: BlockFace) -> bool {
+        match face {
+            BlockFace::Left => self.left,
+            BlockFace::Right => self.right,
+            BlockFace::Top => self.top,
+            BlockFace::Bottom => self.bottom,
+            BlockFace::Front => self.front,
+            BlockFace::Back => self.back,
+        }
+    }
+}
+
+/// Renders a block into the mesh.
+///
+/// Faces on the sides of the block are shaded by the occlusion and light in front of them, while
+/// faces inside it get the light of the block itself.
+pub fn render_block(
+    block: Block,
+    facing: BlockFace,
+    textures: &BlockTextures,
+    models: &BlockModels,
+    chunk: &mut MeshBuilder,
+    position: Vec3,
+    faces: BlockFaces,
+    light: Light,
+    shading: impl Fn(BlockFace) -> (FaceOcclusion, Light),
+    connected: impl Fn(BlockFace) -> bool,
+) {
+    match block.model(models) {
+        BlockModel::Boxes { .. } => {
+            for model_box in block.boxes(models, facing, connected) {
+                render_box(
+                    block, facing, textures, chunk, position, &model_box, &faces, light, &shading,
+                );
+            }
+        }
+        BlockModel::Cross => render_cross(block, textures, chunk, position, light),
+    }
+}
+
+fn render_box(
+    block: Block,
+    facing: BlockFace,
+    textures: &BlockTextures,
+    chunk: &mut MeshBuilder,
+    position: Vec3,
+    model_box: &ModelBox,
+    faces: &BlockFaces,
+    light: Light,
+    shading: &impl Fn(BlockFace) -> (FaceOcclusion, Light),
+) {
+    render_faces(chunk, position, model_box.min, model_box.max, |face| {
+        let visible = !model_box.touches(face) || faces.contains(face);
+        visible.then(|| {
+            let (occlusion, light) = if model_box.touches(face) {
+                shading(face)
+            } else {
+                (FaceOcclusion::NONE, light)
+            };
+            FaceStyle {
+                texture: textures.face_texture(block, face, facing),
+                occlusion,
+                light,
+            }
+        })
+    });
+}
 
-    let tex = Vec2::ZERO;
-    let right = Vec2::X;
-    let bottom = Vec2::Y;
-    let br = right + bottom;
+/// How a face is textured and shaded.
+#[derive(Debug, Clone, Copy)]
+pub struct FaceStyle {
+    pub texture: FaceTexture,
+    pub occlusion: FaceOcclusion,
+    pub light: Light,
+}
+
+/// Emits the faces of an axis-aligned box, skipping any without a style. Textures are projected
+/// from the block grid by the shader, so a box spanning several blocks repeats them across it.
+pub fn render_faces(
+    chunk: &mut MeshBuilder,
+    position: Vec3,
+    min: Vec3,
+    max: Vec3,
+    style: impl Fn(BlockFace) -> Option<FaceStyle>,
+) {
+    let (x0, y0, z0) = (position.x + min.x, position.y + min.y, position.z + min.z);
+    let (x1, y1, z1) = (position.x + max.x, position.y + max.y, position.z + max.z);
 
     // Left
-    if faces.left {
-        let idx = block.face_index(BlockFace::Left);
-        let a = chunk.vertex(vec3(x, y, z), vec3(-1.0, 0.0, 0.0), tex + bottom, idx);
-        let b = chunk.vertex(vec3(x, y + 1.0, z), vec3(-1.0, 0.0, 0.0), tex, idx);
-        let c = chunk.vertex(
-            vec3(x, y + 1.0, z + 1.0),
-            vec3(-1.0, 0.0, 0.0),
-            tex + right,
-            idx,
-        );
-        let d = chunk.vertex(vec3(x, y, z + 1.0), vec3(-1.0, 0.0, 0.0), tex + br, idx);
-        chunk.indices([a, d, c, c, b, a]);
+    if let Some(style) = style(BlockFace::Left) {
+        let corners = [
+            vec3(x0, y0, z0),
+            vec3(x0, y0, z1),
+            vec3(x0, y1, z1),
+            vec3(x0, y1, z0),
+        ];
+        quad(chunk, BlockFace::Left, corners, style, false);
     }
 
     // Right
-    if faces.right {
-        let idx = block.face_index(BlockFace::Right);
-        let a = chunk.vertex(vec3(x + 1.0, y, z), vec3(1.0, 0.0, 0.0), tex + bottom, idx);
-        let b = chunk.vertex(vec3(x + 1.0, y + 1.0, z), vec3(1.0, 0.0, 0.0), tex, idx);
-        let c = chunk.vertex(
-            vec3(x + 1.0, y + 1.0, z + 1.0),
-            vec3(1.0, 0.0, 0.0),
-            tex + right,
-            idx,
-        );
-        let d = chunk.vertex(
-            vec3(x + 1.0, y, z + 1.0),
-            vec3(1.0, 0.0, 0.0),
-            tex + br,
-            idx,
-        );
-        chunk.indices([a, b, c, c, d, a]);
+    if let Some(style) = style(BlockFace::Right) {
+        let corners = [
+            vec3(x1, y0, z0),
+            vec3(x1, y0, z1),
+            vec3(x1, y1, z1),
+            vec3(x1, y1, z0),
+        ];
+        quad(chunk, BlockFace::Right, corners, style, true);
     }
 
     // Top
-    if faces.top {
-        let idx = block.face_index(BlockFace::Top);
-        let a = chunk.vertex(vec3(x, y + 1.0, z), vec3(0.0, 1.0, 0.0), tex, idx);
-        let b = chunk.vertex(
-            vec3(x + 1.0, y + 1.0, z),
-            vec3(0.0, 1.0, 0.0),
-            tex + bottom,
-            idx,
-        );
-        let c = chunk.vertex(
-            vec3(x + 1.0, y + 1.0, z + 1.0),
-            vec3(0.0, 1.0, 0.0),
-            tex + br,
-            idx,
-        );
-        let d = chunk.vertex(
-            vec3(x, y + 1.0, z + 1.0),
-            vec3(0.0, 1.0, 0.0),
-            tex + right,
-            idx,
-        );
-        chunk.indices([a, d, c, c, b, a]);
+    if let Some(style) = style(BlockFace::Top) {
+        let corners = [
+            vec3(x0, y1, z0),
+            vec3(x1, y1, z0),
+            vec3(x1, y1, z1),
+            vec3(x0, y1, z1),
+        ];
+        quad(chunk, BlockFace::Top, corners, style, true);
     }
 
     // Bottom
-    if faces.bottom {
-        let idx = block.face_index(BlockFace::Bottom);
-        let a = chunk.vertex(vec3(x, y, z), vec3(0.0, -1.0, 0.0), tex, idx);
-        let b = chunk.vertex(vec3(x + 1.0, y, z), vec3(0.0, -1.0, 0.0), tex + bottom, idx);
-        let c = chunk.vertex(
-            vec3(x + 1.0, y, z + 1.0),
-            vec3(0.0, -1.0, 0.0),
-            tex + br,
-            idx,
-        );
-        let d = chunk.vertex(vec3(x, y, z + 1.0), vec3(0.0, -1.0, 0.0), tex + right, idx);
-        chunk.indices([a, b, c, c, d, a]);
+    if let Some(style) = style(BlockFace::Bottom) {
+        let corners = [
+            vec3(x0, y0, z0),
+            vec3(x1, y0, z0),
+            vec3(x1, y0, z1),
+            vec3(x0, y0, z1),
+        ];
+        quad(chunk, BlockFace::Bottom, corners, style, false);
     }
 
     // Front
-    if faces.front {
-        let idx = block.face_index(BlockFace::Front);
-        let a = chunk.vertex(vec3(x, y, z + 1.0), vec3(0.0, 0.0, 1.0), tex + bottom, idx);
-        let b = chunk.vertex(
-            vec3(x + 1.0, y, z + 1.0),
-            vec3(0.0, 0.0, 1.0),
-            tex + br,
-            idx,
-        );
-        let c = chunk.vertex(
-            vec3(x + 1.0, y + 1.0, z + 1.0),
-            vec3(0.0, 0.0, 1.0),
-            tex + right,
-            idx,
-        );
-        let d = chunk.vertex(vec3(x, y + 1.0, z + 1.0), vec3(0.0, 0.0, 1.0), tex, idx);
-        chunk.indices([a, b, c, c, d, a]);
+    if let Some(style) = style(BlockFace::Front) {
+        let corners = [
+            vec3(x0, y0, z1),
+            vec3(x1, y0, z1),
+            vec3(x1, y1, z1),
+            vec3(x0, y1, z1),
+        ];
+        quad(chunk, BlockFace::Front, corners, style, false);
     }
 
     // Back
-    if faces.back {
-        let idx = block.face_index(BlockFace::Back);
-        let a = chunk.vertex(vec3(x, y, z), vec3(0.0, 0.0, -1.0), tex + bottom, idx);
-        let b = chunk.vertex(vec3(x + 1.0, y, z), vec3(0.0, 0.0, -1.0), tex + br, idx);
-        let c = chunk.vertex(
-            vec3(x + 1.0, y + 1.0, z),
-            vec3(0.0, 0.0, -1.0),
-            tex + right,
-            idx,
-        );
-        let d = chunk.vertex(vec3(x, y + 1.0, z), vec3(0.0, 0.0, -1.0), tex, idx);
-        chunk.indices([a, d, c, c, b, a]);
+    if let Some(style) = style(BlockFace::Back) {
+        let corners = [
+            vec3(x0, y0, z0),
+            vec3(x1, y0, z0),
+            vec3(x1, y1, z0),
+            vec3(x0, y1, z0),
+        ];
+        quad(chunk, BlockFace::Back, corners, style, true);
+    }
+}
+
+/// Emits a face from its corners, in the order [`FaceOcclusion`] uses for them. Faces where that
+/// order runs clockwise when seen from the front are wound the other way around.
+fn quad(
+    chunk: &mut MeshBuilder,
+    face: BlockFace,
+    corners: [Vec3; 4],
+    style: FaceStyle,
+    clockwise: bool,
+) {
+    let FaceStyle {
+        texture,
+        occlusion,
+        light,
+    } = style;
+
+    let [a, b, c, d] = [0, 1, 2, 3].map(|corner| {
+        let surface = Surface::Face(face);
+        chunk.vertex(
+            corners[corner],
+            surface,
+            texture,
+            occlusion.0[corner],
+            light,
+        )
+    });
+    let [a, b, c, d] = if clockwise {
+        [a, d, c, b]
+    } else {
+        [a, b, c, d]
+    };
+
+    if occlusion.flipped() {
+        chunk.indices([b, c, d, d, a, b]);
+    } else {
+        chunk.indices([a, b, c, c, d, a]);
+    }
+}
+
+fn render_cross(
+    block: Block,
+    textures: &BlockTextures,
+    chunk: &mut MeshBuilder,
+    position: Vec3,
+    light: Light,
+) {
+    // The quads run diagonally through the block, so turning their texture would look wrong.
+    let texture = FaceTexture {
+        rotate: false,
+        ..textures.face_texture(block, BlockFace::Front, BlockFace::default())
+    };
+
+    // Both diagonals are emitted with each winding, so they can be seen from either side.
+    for (start, end) in [(Vec3::ZERO, vec3(1.0, 0.0, 1.0)), (Vec3::Z, Vec3::X)] {
+        let [a, b, c, d] = [start, end, end + Vec3::Y, start + Vec3::Y].map(|corner| {
+            chunk.vertex(
+                position + corner,
+                Surface::Cross,
+                texture,
+                FaceOcclusion::OPEN,
+                light,
+            )
+        });
+        chunk.indices([a, b, c, c, d, a, a, d, c, c, b, a]);
     }
 }
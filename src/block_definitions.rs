@@ -0,0 +1,235 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::{
+    block::{Block, BlockFace},
+    block_model::{BlockModel, CUBE},
+};
+
+/// The textures and models used by each block type, loaded from a `.blocks.ron` file.
+#[derive(Asset, TypePath, Deserialize)]
+pub struct BlockDefinitions {
+    blocks: HashMap<Block, BlockDefinition>,
+}
+
+#[derive(Deserialize)]
+struct BlockDefinition {
+    /// Used for every face that isn't listed in `faces`.
+    textures: Vec<String>,
+    #[serde(default)]
+    rotate: bool,
+    #[serde(default)]
+    faces: HashMap<BlockFace, FaceDefinition>,
+    /// The shape of the block, a full cube if left out.
+    #[serde(default)]
+    model: BlockModel,
+}
+
+#[derive(Deserialize)]
+struct FaceDefinition {
+    /// One of these is picked per block position.
+    textures: Vec<String>,
+    /// Whether the texture can be turned without looking wrong.
+    #[serde(default)]
+    rotate: bool,
+}
+
+impl BlockDefinitions {
+    /// The texture of every layer in the block array. The variants of a face are kept in
+    /// consecutive layers, so a texture can be referred to by its first layer and their number.
+    pub fn texture_names(&self) -> Vec<&str> {
+        self.variant_lists()
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Every distinct list of texture variants used by a face, cut down to as many variants as
+    /// a face can have.
+    fn variant_lists(&self) -> Vec<&[String]> {
+        self.blocks
+            .values()
+            .flat_map(|block| {
+                block
+                    .faces
+                    .values()
+                    .map(|face| &face.textures)
+                    .chain([&block.textures])
+            })
+            .map(|textures| &textures[..textures.len().min(FaceTexture::MAX_VARIANTS)])
+            .filter(|textures| !textures.is_empty())
+            .sorted()
+            .dedup()
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct BlockDefinitionsLoader;
+
+impl AssetLoader for BlockDefinitionsLoader {
+    type Asset = BlockDefinitions;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["blocks.ron"]
+    }
+}
+
+/// The texture of a face, as the first of its variants in the block array and the number of
+/// them. The variant and rotation are picked per block when drawing, so every block of a merged
+/// quad can still look different.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaceTexture {
+    pub layer: u32,
+    /// Between 1 and [`FaceTexture::MAX_VARIANTS`].
+    pub variants: u32,
+    /// Whether the texture can be turned without looking wrong.
+    pub rotate: bool,
+}
+
+impl Default for FaceTexture {
+    fn default() -> Self {
+        Self {
+            layer: 0,
+            variants: 1,
+            rotate: false,
+        }
+    }
+}
+
+impl FaceTexture {
+    pub const MAX_VARIANTS: usize = 8;
+
+    /// Packs the texture into 13 bits: whether it rotates, the number of variants minus one in 3
+    /// bits, and the first layer in 9 bits.
+    pub fn to_bits(self) -> u32 {
+        debug_assert!(self.layer < 512);
+        debug_assert!((1..=Self::MAX_VARIANTS as u32).contains(&self.variants));
+        self.rotate as u32 | (self.variants - 1) << 1 | self.layer << 4
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        Self {
+            layer: bits >> 4 & 0x1ff,
+            variants: (bits >> 1 & 7) + 1,
+            rotate: bits & 1 != 0,
+        }
+    }
+
+    /// The layer and number of quarter turns used for the texture on the given surface of a
+    /// block, the same way `chunk.wgsl` picks them.
+    pub fn pick(self, block: IVec3, surface: u32) -> (u32, u32) {
+        let hash = texture_hash(block, surface);
+        let quarter_turns = if self.rotate { hash >> 8 & 3 } else { 0 };
+        (self.layer + hash % self.variants, quarter_turns)
+    }
+}
+
+/// Mixes the position of a block and a surface of it into a hash, matching `texture_hash` in
+/// `chunk.wgsl`.
+fn texture_hash(block: IVec3, surface: u32) -> u32 {
+    let hash = (block.x as u32).wrapping_mul(0x8da6_b343)
+        ^ (block.y as u32).wrapping_mul(0xd816_3841)
+        ^ (block.z as u32).wrapping_mul(0xcb1a_b31f)
+        ^ (surface + 1).wrapping_mul(0x9e37_79b9);
+    let hash = (hash ^ hash >> 16).wrapping_mul(0x7feb_352d);
+    let hash = (hash ^ hash >> 15).wrapping_mul(0x846c_a68b);
+    hash ^ hash >> 16
+}
+
+/// The block definitions resolved to texture layers, cheap to clone into meshing tasks.
+#[derive(Resource, Clone, Default)]
+pub struct BlockTextures(Arc<HashMap<(Block, BlockFace), FaceTexture>>);
+
+impl BlockTextures {
+    pub fn new(definitions: &BlockDefinitions) -> Self {
+        let lists = definitions.variant_lists();
+        let first_layers: Vec<u32> = lists
+            .iter()
+            .scan(0, |layer, list| {
+                let first = *layer;
+                *layer += list.len() as u32;
+                Some(first)
+            })
+            .collect();
+        let texture = |textures: &[String], rotate: bool| {
+            let textures = &textures[..textures.len().min(FaceTexture::MAX_VARIANTS)];
+            let index = lists.iter().position(|&list| list == textures)?;
+            Some(FaceTexture {
+                layer: first_layers[index],
+                variants: textures.len() as u32,
+                rotate,
+            })
+        };
+
+        let mut faces = HashMap::new();
+
+        for (&block, definition) in definitions.blocks.iter() {
+            for face in BlockFace::ALL {
+                let face_texture = match definition.faces.get(&face) {
+                    Some(face) => texture(&face.textures, face.rotate),
+                    None => texture(&definition.textures, definition.rotate),
+                };
+
+                if let Some(face_texture) = face_texture {
+                    faces.insert((block, face), face_texture);
+                }
+            }
+        }
+
+        Self(Arc::new(faces))
+    }
+
+    /// The texture of a face of a block placed facing the given way.
+    pub fn face_texture(&self, block: Block, face: BlockFace, facing: BlockFace) -> FaceTexture {
+        let local_face = block.local_face(face, facing);
+        self.0
+            .get(&(block, local_face))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// The models from the block definitions, cheap to clone into meshing and generation tasks.
+#[derive(Resource, Clone, Default)]
+pub struct BlockModels(Arc<HashMap<Block, BlockModel>>);
+
+impl BlockModels {
+    pub fn new(definitions: &BlockDefinitions) -> Self {
+        let models = definitions
+            .blocks
+            .iter()
+            .map(|(&block, definition)| (block, definition.model.clone()))
+            .collect();
+
+        Self(Arc::new(models))
+    }
+
+    /// The model of a block, a cube if the definitions don't give it one.
+    pub fn get(&self, block: Block) -> &BlockModel {
+        self.0.get(&block).unwrap_or(&CUBE)
+    }
+}
@@ -0,0 +1,128 @@
+use std::borrow::Cow;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::block::BlockFace;
+
+/// An axis-aligned box inside the unit cube of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(from = "BoxBounds")]
+pub struct ModelBox {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+/// How boxes are written in the block definitions, as `(min: (x, y, z), max: (x, y, z))`.
+#[derive(Deserialize)]
+struct BoxBounds {
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+impl From<BoxBounds> for ModelBox {
+    fn from(bounds: BoxBounds) -> Self {
+        Self::new(Vec3::from(bounds.min), Vec3::from(bounds.max))
+    }
+}
+
+impl ModelBox {
+    pub const fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// Turns the box around the vertical center of the block, moving its front towards the right.
+    pub fn turned(self, quarter_turns: usize) -> Self {
+        let mut model_box = self;
+        for _ in 0..quarter_turns % 4 {
+            let (min, max) = (model_box.min, model_box.max);
+            model_box = Self::new(
+                Vec3::new(min.z, min.y, 1.0 - max.x),
+                Vec3::new(max.z, max.y, 1.0 - min.x),
+            );
+        }
+        model_box
+    }
+
+    /// Whether a face of the box lies on the boundary of the block, where a neighbor can hide it.
+    pub fn touches(&self, face: BlockFace) -> bool {
+        match face {
+            BlockFace::Left => self.min.x == 0.0,
+            BlockFace::Right => self.max.x == 1.0,
+            BlockFace::Top => self.max.y == 1.0,
+            BlockFace::Bottom => self.min.y == 0.0,
+            BlockFace::Front => self.max.z == 1.0,
+            BlockFace::Back => self.min.z == 0.0,
+        }
+    }
+
+    /// Whether the box fills the entire face of the block on the given side.
+    pub fn covers(&self, face: BlockFace) -> bool {
+        let full_x = self.min.x == 0.0 && self.max.x == 1.0;
+        let full_y = self.min.y == 0.0 && self.max.y == 1.0;
+        let full_z = self.min.z == 0.0 && self.max.z == 1.0;
+
+        self.touches(face)
+            && match face {
+                BlockFace::Left | BlockFace::Right => full_y && full_z,
+                BlockFace::Top | BlockFace::Bottom => full_x && full_z,
+                BlockFace::Front | BlockFace::Back => full_x && full_y,
+            }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum BlockModel {
+    /// A solid shape made out of one or more boxes.
+    Boxes {
+        boxes: Cow<'static, [ModelBox]>,
+        /// Boxes reaching out to a side, only added when the neighbor there connects to the
+        /// block, such as the rails of a fence.
+        #[serde(default)]
+        connections: Cow<'static, [(BlockFace, ModelBox)]>,
+        /// The sides on which the model hides the faces of its neighbors, such as
+        /// `culls: Some([Bottom])`. Left out, these are the sides fully covered by a box.
+        #[serde(default)]
+        culls: Option<Cow<'static, [BlockFace]>>,
+    },
+    /// Two intersecting diagonal quads, used for plants.
+    Cross,
+}
+
+impl Default for BlockModel {
+    fn default() -> Self {
+        CUBE.clone()
+    }
+}
+
+impl BlockModel {
+    /// A model made of boxes that always hide the faces they fully cover.
+    const fn boxes(boxes: &'static [ModelBox]) -> Self {
+        Self::Boxes {
+            boxes: Cow::Borrowed(boxes),
+            connections: Cow::Borrowed(&[]),
+            culls: None,
+        }
+    }
+
+    /// Whether the model fully hides the face of a neighbor touching the given side.
+    pub fn occludes(&self, face: BlockFace) -> bool {
+        match self {
+            Self::Boxes {
+                culls: Some(culls), ..
+            } => culls.contains(&face),
+            Self::Boxes { boxes, .. } => boxes.iter().any(|model_box| model_box.covers(face)),
+            Self::Cross => false,
+        }
+    }
+
+    pub fn has_collision(&self) -> bool {
+        match self {
+            Self::Boxes { .. } => true,
+            Self::Cross => false,
+        }
+    }
+}
+
+/// The model of blocks the definitions don't give one.
+pub static CUBE: BlockModel = BlockModel::boxes(&[ModelBox::new(Vec3::ZERO, Vec3::ONE)]);
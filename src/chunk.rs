@@ -1,14 +1,26 @@
+use std::sync::Arc;
+
 use bevy::prelude::*;
 use bevy_xpbd_3d::components::Collider;
 use itertools::Itertools;
+use parking_lot::RwLock;
 
 use crate::{
-    block::{render_cube, BlockFaces},
+    ambient_occlusion::{FaceOcclusion, Occluders},
+    block::{render_block, render_faces, Block, BlockFace, BlockFaces, BlockLayer, FaceStyle},
+    block_definitions::{BlockModels, BlockTextures, FaceTexture},
+    block_model::CUBE,
+    chunk_collider::build_collider,
+    chunk_diagnostics::{ChunkDiagnosticsPlugin, ChunkTimings},
+    chunk_visibility::ChunkVisibility,
+    greedy_mesher::GreedyMesher,
+    light::{ChunkLight, Light, LightSampler},
     mesh_builder::MeshBuilder,
     voxel::{
-        chunk::{iter_blocks, Chunk, CHUNK_SIZE},
+        chunk::{Chunk, CHUNK_SIZE},
         chunk_data::ChunkData,
         chunk_index::ChunkIndex,
+        chunk_sections::ChunkSections,
     },
 };
 
@@ -19,87 +31,62 @@ pub struct AdjacentChunks {
     pub bottom: Option<Chunk>,
     pub front: Option<Chunk>,
     pub back: Option<Chunk>,
+    /// The level of detail each of them is meshed at, in the order of the fields above.
+    pub lods: [u32; 6],
 }
 
 impl AdjacentChunks {
-    pub fn compute_edges(self) -> AdjacentEdges {
+    /// The chunks as they're meshed next to a chunk at the given level of detail. Surfaces at
+    /// different levels don't line up, so each side is culled against the neighbor at the coarser
+    /// of both levels, which is what the coarser one of the two draws there.
+    fn downsampled(self, lod: u32, models: &BlockModels) -> Self {
+        let downsample = |chunk: Option<Chunk>, neighbor_lod: u32| {
+            let lod = lod.max(neighbor_lod);
+            if lod == 0 {
+                return chunk;
+            }
+            chunk.map(|chunk| downsample(&chunk, 1 << lod, models))
+        };
+        let [left, right, top, bottom, front, back] = self.lods;
+
+        Self {
+            left: downsample(self.left, left),
+            right: downsample(self.right, right),
+            top: downsample(self.top, top),
+            bottom: downsample(self.bottom, bottom),
+            front: downsample(self.front, front),
+            back: downsample(self.back, back),
+            lods: self.lods,
+        }
+    }
+
+    pub fn compute_edges(&self) -> AdjacentEdges {
         let positions = (0..CHUNK_SIZE).cartesian_product(0..CHUNK_SIZE);
 
-        AdjacentEdges {
-            left: {
-                let mut values = [true; CHUNK_SIZE * CHUNK_SIZE];
-                if let Some(left) = self.left {
-                    for (y, z) in positions.clone() {
-                        let is_block = left
-                            .read()
-                            .block(ChunkIndex::new(CHUNK_SIZE - 1, y, z))
-                            .is_some();
-                        values[AdjacentEdges::index(y, z)] = is_block;
-                    }
-                }
-                values
-            },
-            right: {
-                let mut values = [true; CHUNK_SIZE * CHUNK_SIZE];
-                if let Some(right) = self.right {
-                    for (y, z) in positions.clone() {
-                        let is_block = right.read().block(ChunkIndex::new(0, y, z)).is_some();
-                        values[AdjacentEdges::index(y, z)] = is_block;
-                    }
-                }
-                values
-            },
-            top: {
-                let mut values = [true; CHUNK_SIZE * CHUNK_SIZE];
-                if let Some(top) = self.top {
-                    for (x, z) in positions.clone() {
-                        let is_block = top.read().block(ChunkIndex::new(x, 0, z)).is_some();
-                        values[AdjacentEdges::index(x, z)] = is_block;
-                    }
-                }
-                values
-            },
-            bottom: {
-                let mut values = [true; CHUNK_SIZE * CHUNK_SIZE];
-                if let Some(bottom) = self.bottom {
-                    for (x, z) in positions.clone() {
-                        let is_block = bottom
-                            .read()
-                            .block(ChunkIndex::new(x, CHUNK_SIZE - 1, z))
-                            .is_some();
-                        values[AdjacentEdges::index(x, z)] = is_block;
-                    }
-                }
-                values
-            },
-            front: {
-                let mut values = [true; CHUNK_SIZE * CHUNK_SIZE];
-                if let Some(front) = self.front {
-                    for (x, y) in positions.clone() {
-                        let is_block = front.read().block(ChunkIndex::new(x, y, 0)).is_some();
-                        values[AdjacentEdges::index(x, y)] = is_block;
-                    }
+        let edge = |chunk: &Option<Chunk>, index: &dyn Fn(usize, usize) -> ChunkIndex| {
+            chunk.as_ref().map(|chunk| {
+                let chunk = chunk.read();
+                let mut values = [None; CHUNK_SIZE * CHUNK_SIZE];
+                for (a, b) in positions.clone() {
+                    values[AdjacentEdges::index(a, b)] = chunk.block(index(a, b));
                 }
                 values
-            },
-            back: {
-                let mut values = [true; CHUNK_SIZE * CHUNK_SIZE];
-                if let Some(back) = self.back {
-                    for (x, y) in positions.clone() {
-                        let is_block = back
-                            .read()
-                            .block(ChunkIndex::new(x, y, CHUNK_SIZE - 1))
-                            .is_some();
-                        values[AdjacentEdges::index(x, y)] = is_block;
-                    }
-                }
-                values
-            },
+            })
+        };
+
+        AdjacentEdges {
+            left: edge(&self.left, &|y, z| ChunkIndex::new(CHUNK_SIZE - 1, y, z)),
+            right: edge(&self.right, &|y, z| ChunkIndex::new(0, y, z)),
+            top: edge(&self.top, &|x, z| ChunkIndex::new(x, 0, z)),
+            bottom: edge(&self.bottom, &|x, z| ChunkIndex::new(x, CHUNK_SIZE - 1, z)),
+            front: edge(&self.front, &|x, y| ChunkIndex::new(x, y, 0)),
+            back: edge(&self.back, &|x, y| ChunkIndex::new(x, y, CHUNK_SIZE - 1)),
         }
     }
 }
 
-type AdjacentEdge = [bool; CHUNK_SIZE * CHUNK_SIZE];
+/// The blocks on the touching side of an adjacent chunk, or `None` if it isn't loaded.
+type AdjacentEdge = Option<[Option<Block>; CHUNK_SIZE * CHUNK_SIZE]>;
 
 pub struct AdjacentEdges {
     left: AdjacentEdge,
@@ -111,73 +98,415 @@ pub struct AdjacentEdges {
 }
 
 impl AdjacentEdges {
-    pub fn compute_faces(&self, chunk: &ChunkData, x: usize, y: usize, z: usize) -> BlockFaces {
+    pub fn compute_faces(
+        &self,
+        chunk: &ChunkData,
+        models: &BlockModels,
+        block: Block,
+        x: usize,
+        y: usize,
+        z: usize,
+    ) -> BlockFaces {
+        let inner = |face, x, y, z| {
+            block.is_face_visible(models, face, chunk.block(ChunkIndex::new(x, y, z)))
+        };
+        let edge = |face, edge: &AdjacentEdge, a, b| Self::edge(models, block, face, edge, a, b);
+
         BlockFaces {
             left: if x == 0 {
-                !self.left[Self::index(y, z)]
+                edge(BlockFace::Left, &self.left, y, z)
             } else {
-                chunk.block(ChunkIndex::new(x - 1, y, z)).is_none()
+                inner(BlockFace::Left, x - 1, y, z)
             },
             right: if x == CHUNK_SIZE - 1 {
-                !self.right[Self::index(y, z)]
+                edge(BlockFace::Right, &self.right, y, z)
             } else {
-                chunk.block(ChunkIndex::new(x + 1, y, z)).is_none()
+                inner(BlockFace::Right, x + 1, y, z)
             },
             top: if y == CHUNK_SIZE - 1 {
-                !self.top[Self::index(x, z)]
+                edge(BlockFace::Top, &self.top, x, z)
             } else {
-                chunk.block(ChunkIndex::new(x, y + 1, z)).is_none()
+                inner(BlockFace::Top, x, y + 1, z)
             },
             bottom: if y == 0 {
-                !self.bottom[Self::index(x, z)]
+                edge(BlockFace::Bottom, &self.bottom, x, z)
             } else {
-                chunk.block(ChunkIndex::new(x, y - 1, z)).is_none()
+                inner(BlockFace::Bottom, x, y - 1, z)
             },
             front: if z == CHUNK_SIZE - 1 {
-                !self.front[Self::index(x, y)]
+                edge(BlockFace::Front, &self.front, x, y)
             } else {
-                chunk.block(ChunkIndex::new(x, y, z + 1)).is_none()
+                inner(BlockFace::Front, x, y, z + 1)
             },
             back: if z == 0 {
-                !self.back[Self::index(x, y)]
+                edge(BlockFace::Back, &self.back, x, y)
             } else {
-                chunk.block(ChunkIndex::new(x, y, z - 1)).is_none()
+                inner(BlockFace::Back, x, y, z - 1)
             },
         }
     }
 
+    /// The block next to a block of the chunk on the given side, looking into the adjacent chunk
+    /// at the border. `None` if there's nothing there or that chunk isn't loaded.
+    pub fn neighbor(&self, chunk: &ChunkData, index: ChunkIndex, face: BlockFace) -> Option<Block> {
+        if let Some(index) = index.neighbor(face) {
+            return chunk.block(index);
+        }
+
+        let (x, y, z) = index.xyz();
+        let (edge, a, b) = match face {
+            BlockFace::Left => (&self.left, y, z),
+            BlockFace::Right => (&self.right, y, z),
+            BlockFace::Top => (&self.top, x, z),
+            BlockFace::Bottom => (&self.bottom, x, z),
+            BlockFace::Front => (&self.front, x, y),
+            BlockFace::Back => (&self.back, x, y),
+        };
+        edge.as_ref()?[Self::index(a, b)]
+    }
+
+    // Faces against chunks which haven't loaded yet stay hidden until they do.
+    fn edge(
+        models: &BlockModels,
+        block: Block,
+        face: BlockFace,
+        edge: &AdjacentEdge,
+        a: usize,
+        b: usize,
+    ) -> bool {
+        edge.as_ref()
+            .is_some_and(|edge| block.is_face_visible(models, face, edge[Self::index(a, b)]))
+    }
+
     fn index(a: usize, b: usize) -> usize {
         a + b * CHUNK_SIZE
     }
 }
 
-pub async fn generate_mesh(chunk: Chunk, adjacent: AdjacentChunks) -> (Mesh, Option<Collider>) {
-    let mut mesh_builder = MeshBuilder::new();
-    let edges = adjacent.compute_edges();
-    let chunk = chunk.read();
+pub struct ChunkMeshes {
+    pub opaque: Mesh,
+    pub cutout: Mesh,
+    pub translucent: Mesh,
+}
 
-    for (x, y, z) in iter_blocks() {
-        let Some(block) = chunk.block(ChunkIndex::new(x, y, z)) else {
-            continue;
-        };
+/// The algorithm used to turn chunks into meshes.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mesher {
+    /// Two triangles for every visible face.
+    Naive,
+    /// Merges neighboring faces of full blocks with the same texture into larger quads.
+    #[default]
+    Greedy,
+}
 
-        let faces = edges.compute_faces(&chunk, x, y, z);
-        render_cube(
-            block,
-            &mut mesh_builder,
-            Vec3::new(x as f32, y as f32, z as f32),
-            faces,
-        );
+#[derive(Default)]
+struct LayerBuilders {
+    opaque: MeshBuilder,
+    cutout: MeshBuilder,
+    translucent: MeshBuilder,
+}
+
+impl LayerBuilders {
+    fn get(&mut self, layer: BlockLayer) -> &mut MeshBuilder {
+        match layer {
+            BlockLayer::Opaque => &mut self.opaque,
+            BlockLayer::Cutout => &mut self.cutout,
+            BlockLayer::Translucent => &mut self.translucent,
+        }
+    }
+
+    fn build(self) -> ChunkMeshes {
+        ChunkMeshes {
+            opaque: self.opaque.build(),
+            cutout: self.cutout.build(),
+            translucent: self.translucent.build(),
+        }
     }
+}
+
+/// Packs everything that has to match for two faces to be merged into a non-zero key.
+///
+/// A merged quad only interpolates occlusion between its own corners, so faces whose occlusion
+/// changes along an axis also get their position on that axis in the key, to keep them from
+/// merging in that direction.
+///
+/// Texture variants and rotations are picked per block by the shader, so only the face's
+/// texture itself has to match and merged quads still vary from block to block.
+fn face_key(
+    layer: BlockLayer,
+    texture: FaceTexture,
+    occlusion: FaceOcclusion,
+    light: Light,
+    face: BlockFace,
+    x: usize,
+    y: usize,
+    z: usize,
+) -> u64 {
+    let layer = match layer {
+        BlockLayer::Opaque => 0,
+        BlockLayer::Cutout => 1,
+        BlockLayer::Translucent => 2,
+    };
+    let (u, v) = match face {
+        BlockFace::Left | BlockFace::Right => (z, y),
+        BlockFace::Top | BlockFace::Bottom => (x, z),
+        BlockFace::Front | BlockFace::Back => (x, y),
+    };
+    let u = if occlusion.varies_along_u() {
+        u as u64 + 1
+    } else {
+        0
+    };
+    let v = if occlusion.varies_along_v() {
+        v as u64 + 1
+    } else {
+        0
+    };
+
+    let key = texture.to_bits() as u64;
+    let key = key << 8 | occlusion.to_bits() as u64;
+    let key = key << 16 | light.to_bits() as u64;
+    let key = (key << 6 | u) << 6 | v;
+    (key << 2 | layer) + 1
+}
+
+fn unpack_face_key(key: u64) -> (BlockLayer, FaceStyle) {
+    let key = key - 1;
+    let layer = match key & 3 {
+        0 => BlockLayer::Opaque,
+        1 => BlockLayer::Cutout,
+        _ => BlockLayer::Translucent,
+    };
+    let key = key >> 14;
+    let light = Light::from_bits((key & 0xffff) as u32);
+    let key = key >> 16;
+    let occlusion = FaceOcclusion::from_bits((key & 0xff) as u32);
+    let key = key >> 8;
+    let style = FaceStyle {
+        texture: FaceTexture::from_bits(key as u32),
+        occlusion,
+        light,
+    };
+    (layer, style)
+}
+
+/// Draws a cube of `scale` blocks of a downsampled chunk as a single box. Occlusion is only known
+/// per block, so it's left out of faces this large. Each face is styled for the block of the cube
+/// it's on the side of.
+fn render_cell(
+    builder: &mut MeshBuilder,
+    edges: &AdjacentEdges,
+    chunk: &ChunkData,
+    models: &BlockModels,
+    block: Block,
+    scale: usize,
+    (x, y, z): (usize, usize, usize),
+    style: impl Fn(BlockFace, (usize, usize, usize)) -> FaceStyle,
+) {
+    // The whole cube is the same block, so the sides facing away from its first block are found
+    // next to its last one.
+    let last = scale - 1;
+    let far_block = (x + last, y + last, z + last);
+    let near = edges.compute_faces(chunk, models, block, x, y, z);
+    let far = edges.compute_faces(chunk, models, block, x + last, y + last, z + last);
+    let faces = BlockFaces {
+        right: far.right,
+        top: far.top,
+        front: far.front,
+        ..near
+    };
+
+    let position = Vec3::new(x as f32, y as f32, z as f32);
+    render_faces(
+        builder,
+        position,
+        Vec3::ZERO,
+        Vec3::splat(scale as f32),
+        |face| {
+            let side_of = match face {
+                BlockFace::Right | BlockFace::Top | BlockFace::Front => far_block,
+                _ => (x, y, z),
+            };
+            faces.contains(face).then(|| style(face, side_of))
+        },
+    );
+}
+
+fn downsample(chunk: &Chunk, scale: usize, models: &BlockModels) -> Chunk {
+    Arc::new(RwLock::new(chunk.read().downsampled(scale, models)))
+}
+
+/// What a mesh task rebuilt of a chunk.
+pub struct ChunkBuild {
+    /// The meshes of every section that was asked for.
+    pub sections: Vec<(usize, ChunkMeshes)>,
+    /// The new collider, if its blocks were asked for at all.
+    pub collider: Option<Option<Collider>>,
+    pub visibility: Option<ChunkVisibility>,
+}
+
+/// Builds the meshes of some sections of a chunk at the given level of detail, where every level
+/// halves the resolution, and what depends on its blocks if asked to. Only chunks at full detail
+/// get a collider, which is built from the blocks rather than the meshes.
+pub async fn generate_mesh(
+    chunk: Chunk,
+    adjacent: AdjacentChunks,
+    neighborhood: [Option<Chunk>; 27],
+    lights: [Option<ChunkLight>; 27],
+    textures: BlockTextures,
+    models: BlockModels,
+    mesher: Mesher,
+    lod: u32,
+    sections: ChunkSections,
+    blocks: bool,
+    timings: ChunkTimings,
+) -> ChunkBuild {
+    let collider = blocks.then(|| {
+        (lod == 0)
+            .then(|| {
+                timings.time(ChunkDiagnosticsPlugin::BUILD_COLLIDER, || {
+                    build_collider(&chunk.read(), &adjacent.compute_edges(), &models)
+                })
+            })
+            .flatten()
+    });
+    let visibility = blocks.then(|| ChunkVisibility::new(&chunk.read(), &models));
+
+    timings.time(ChunkDiagnosticsPlugin::GENERATE_MESH, || {
+        let sections = mesh_sections(
+            &chunk,
+            adjacent,
+            neighborhood,
+            lights,
+            &textures,
+            &models,
+            mesher,
+            lod,
+            sections,
+        );
+        ChunkBuild {
+            sections,
+            collider,
+            visibility,
+        }
+    })
+}
 
-    drop(chunk);
-    let mesh = mesh_builder.build();
+fn mesh_sections(
+    chunk: &Chunk,
+    adjacent: AdjacentChunks,
+    neighborhood: [Option<Chunk>; 27],
+    lights: [Option<ChunkLight>; 27],
+    textures: &BlockTextures,
+    models: &BlockModels,
+    mesher: Mesher,
+    lod: u32,
+    sections: ChunkSections,
+) -> Vec<(usize, ChunkMeshes)> {
+    let scale = 1 << lod;
+    let adjacent = adjacent.downsampled(lod, models);
+    let (chunk, neighborhood) = if lod == 0 {
+        (chunk.clone(), neighborhood)
+    } else {
+        (
+            downsample(chunk, scale, models),
+            neighborhood.map(|chunk| chunk.map(|chunk| downsample(&chunk, scale, models))),
+        )
+    };
 
-    let collider = if mesh.count_vertices() > 0 {
-        Collider::trimesh_from_mesh(&mesh)
+    let edges = adjacent.compute_edges();
+    let occluders = Occluders::new(&neighborhood, models);
+    let lights = LightSampler::new(&lights);
+    let lights = if lod == 0 {
+        lights
     } else {
-        None
+        lights.downsampled(scale)
     };
+    let chunk = chunk.read();
+
+    sections
+        .iter()
+        .map(|section| {
+            let mut builders = LayerBuilders::default();
+            let mut greedy = (mesher == Mesher::Greedy)
+                .then(|| GreedyMesher::new(ChunkSections::origin(section)));
+
+            for (x, y, z) in ChunkSections::blocks(section) {
+                // Coarser chunks are made of cubes of `scale` blocks, which the naive mesher
+                // draws whole from their first block.
+                let cell_origin = [x, y, z].iter().all(|v| v % scale == 0);
+                if greedy.is_none() && !cell_origin {
+                    continue;
+                }
+
+                let index = ChunkIndex::new(x, y, z);
+                let Some(block) = chunk.block(index) else {
+                    continue;
+                };
+
+                let facing = chunk.facing(index);
+                let position = Vec3::new(x as f32, y as f32, z as f32);
+                let faces = edges.compute_faces(&chunk, models, block, x, y, z);
+
+                if let Some(greedy) = greedy.as_mut() {
+                    if block.model(models) == &CUBE {
+                        for face in BlockFace::ALL
+                            .into_iter()
+                            .filter(|&face| faces.contains(face))
+                        {
+                            let texture = textures.face_texture(block, face, facing);
+                            let occlusion = occluders.face(x, y, z, face);
+                            let light = lights.face(x, y, z, face);
+                            let key =
+                                face_key(block.layer(), texture, occlusion, light, face, x, y, z);
+                            greedy.add_face(face, x, y, z, key);
+                        }
+                        continue;
+                    }
+                }
+
+                if scale > 1 {
+                    render_cell(
+                        builders.get(block.layer()),
+                        &edges,
+                        &chunk,
+                        models,
+                        block,
+                        scale,
+                        (x, y, z),
+                        |face, (x, y, z)| FaceStyle {
+                            texture: textures.face_texture(block, face, facing),
+                            occlusion: FaceOcclusion::NONE,
+                            light: lights.face(x, y, z, face),
+                        },
+                    );
+                    continue;
+                }
+
+                render_block(
+                    block,
+                    facing,
+                    textures,
+                    models,
+                    builders.get(block.layer()),
+                    position,
+                    faces,
+                    lights.block(x, y, z),
+                    |face| (occluders.face(x, y, z, face), lights.face(x, y, z, face)),
+                    |face| block.connects_to(models, face, edges.neighbor(&chunk, index, face)),
+                );
+            }
+
+            if let Some(greedy) = greedy {
+                greedy.build(|face, position, size, key| {
+                    let (layer, style) = unpack_face_key(key);
+                    render_faces(builders.get(layer), position, Vec3::ZERO, size, |other| {
+                        (other == face).then_some(style)
+                    });
+                });
+            }
 
-    (mesh, collider)
+            (section, builders.build())
+        })
+        .collect()
 }
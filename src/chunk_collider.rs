@@ -0,0 +1,113 @@
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    block_definitions::BlockModels,
+    block_model::CUBE,
+    chunk::AdjacentEdges,
+    voxel::{
+        chunk::{iter_blocks, CHUNK_SIZE},
+        chunk_data::ChunkData,
+        chunk_index::ChunkIndex,
+    },
+};
+
+/// Builds the collider of a chunk straight from its blocks, as a compound of boxes.
+///
+/// Full blocks are merged into as few boxes as possible, growing each box along x, then y, then z
+/// for as long as it stays filled. Blocks with other models add their own boxes, connecting to
+/// the blocks on the touching sides of the adjacent chunks like they do in the mesh.
+pub fn build_collider(
+    chunk: &ChunkData,
+    edges: &AdjacentEdges,
+    models: &BlockModels,
+) -> Option<Collider> {
+    let index = |x: usize, y: usize, z: usize| x + (y + z * CHUNK_SIZE) * CHUNK_SIZE;
+
+    let mut solid = vec![false; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
+    let mut boxes = Vec::new();
+
+    for (x, y, z) in iter_blocks() {
+        let chunk_index = ChunkIndex::new(x, y, z);
+        let Some(block) = chunk
+            .block(chunk_index)
+            .filter(|block| block.has_collision(models))
+        else {
+            continue;
+        };
+
+        if block.model(models) == &CUBE {
+            solid[index(x, y, z)] = true;
+        } else {
+            let position = Vec3::new(x as f32, y as f32, z as f32);
+            let connected =
+                |face| block.connects_to(models, face, edges.neighbor(chunk, chunk_index, face));
+            boxes.extend(
+                block
+                    .boxes(models, chunk.facing(chunk_index), connected)
+                    .map(|model_box| (position + model_box.min, position + model_box.max)),
+            );
+        }
+    }
+
+    for z in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                if !solid[index(x, y, z)] {
+                    continue;
+                }
+
+                let mut width = 1;
+                while x + width < CHUNK_SIZE && solid[index(x + width, y, z)] {
+                    width += 1;
+                }
+
+                let mut height = 1;
+                while y + height < CHUNK_SIZE
+                    && (x..x + width).all(|x| solid[index(x, y + height, z)])
+                {
+                    height += 1;
+                }
+
+                let mut depth = 1;
+                while z + depth < CHUNK_SIZE
+                    && (y..y + height)
+                        .all(|y| (x..x + width).all(|x| solid[index(x, y, z + depth)]))
+                {
+                    depth += 1;
+                }
+
+                for z in z..z + depth {
+                    for y in y..y + height {
+                        for x in x..x + width {
+                            solid[index(x, y, z)] = false;
+                        }
+                    }
+                }
+
+                let min = Vec3::new(x as f32, y as f32, z as f32);
+                let size = Vec3::new(width as f32, height as f32, depth as f32);
+                boxes.push((min, min + size));
+            }
+        }
+    }
+
+    if boxes.is_empty() {
+        return None;
+    }
+
+    Some(Collider::compound(
+        boxes
+            .into_iter()
+            .map(|(min, max)| {
+                let size = (max - min).as_dvec3();
+                let center = ((min + max) / 2.0).as_dvec3();
+                (
+                    Position(center),
+                    Rotation::default(),
+                    Collider::cuboid(size.x, size.y, size.z),
+                )
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,117 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, DiagnosticMeasurement, DiagnosticsStore},
+    prelude::*,
+};
+use parking_lot::Mutex;
+
+/// How many samples of each diagnostic are kept for the histograms in the debug window.
+const HISTORY: usize = 240;
+
+/// Adds diagnostics for what loading and meshing chunks costs.
+pub struct ChunkDiagnosticsPlugin;
+
+impl Plugin for ChunkDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        let timing = |id, name| {
+            Diagnostic::new(id, name, HISTORY)
+                .with_suffix("ms")
+                .with_smoothing_factor(0.0)
+        };
+
+        app.init_resource::<ChunkTimings>()
+            .register_diagnostic(timing(Self::GENERATE_CHUNK, "generate_chunk"))
+            .register_diagnostic(timing(Self::DATABASE_READ, "database_read"))
+            .register_diagnostic(timing(Self::DATABASE_WRITE, "database_write"))
+            .register_diagnostic(timing(Self::GENERATE_MESH, "generate_mesh"))
+            .register_diagnostic(timing(Self::BUILD_COLLIDER, "build_collider"))
+            .register_diagnostic(timing(Self::INSERT_LIGHT, "insert_light"))
+            .register_diagnostic(Diagnostic::new(
+                Self::PENDING_GENERATE_TASKS,
+                "pending_generate_tasks",
+                HISTORY,
+            ))
+            .register_diagnostic(Diagnostic::new(
+                Self::PENDING_MESH_TASKS,
+                "pending_mesh_tasks",
+                HISTORY,
+            ))
+            .register_diagnostic(
+                Diagnostic::new(Self::VERTEX_MEMORY, "vertex_memory", 1).with_suffix("MiB"),
+            )
+            .register_diagnostic(
+                Diagnostic::new(Self::INDEX_MEMORY, "index_memory", 1).with_suffix("MiB"),
+            )
+            .register_diagnostic(Diagnostic::new(Self::QUADS, "quads", 1))
+            .add_systems(PreUpdate, collect_timings);
+    }
+}
+
+impl ChunkDiagnosticsPlugin {
+    pub const GENERATE_CHUNK: DiagnosticId =
+        DiagnosticId::from_u128(64989867744099927373962325275833049344);
+    pub const DATABASE_READ: DiagnosticId =
+        DiagnosticId::from_u128(39914624069858837495340286923554513294);
+    pub const DATABASE_WRITE: DiagnosticId =
+        DiagnosticId::from_u128(111236159080228869349754150934889794759);
+    pub const GENERATE_MESH: DiagnosticId =
+        DiagnosticId::from_u128(309139512712071603662206696498978695659);
+    pub const BUILD_COLLIDER: DiagnosticId =
+        DiagnosticId::from_u128(197317037890559250239360641582015107517);
+    pub const INSERT_LIGHT: DiagnosticId =
+        DiagnosticId::from_u128(125709717419174891424249296126853329273);
+    pub const PENDING_GENERATE_TASKS: DiagnosticId =
+        DiagnosticId::from_u128(31772707185246077940231738424437007916);
+    pub const PENDING_MESH_TASKS: DiagnosticId =
+        DiagnosticId::from_u128(185551514801717129624353445466293694518);
+    pub const VERTEX_MEMORY: DiagnosticId =
+        DiagnosticId::from_u128(204721465604245603040923502496855720412);
+    pub const INDEX_MEMORY: DiagnosticId =
+        DiagnosticId::from_u128(295696386800806817695849132530523290934);
+    /// How many quads the meshes of all chunks are made of, to compare how well meshers merge.
+    pub const QUADS: DiagnosticId =
+        DiagnosticId::from_u128(251130101412649831333851130862559437662);
+
+    /// The diagnostics measured in milliseconds, with the names shown for them.
+    pub const TIMINGS: [(DiagnosticId, &'static str); 6] = [
+        (Self::GENERATE_CHUNK, "Generate chunk"),
+        (Self::DATABASE_READ, "Database read"),
+        (Self::DATABASE_WRITE, "Database write"),
+        (Self::GENERATE_MESH, "Generate mesh"),
+        (Self::BUILD_COLLIDER, "Build collider"),
+        (Self::INSERT_LIGHT, "Insert light"),
+    ];
+}
+
+/// Durations measured inside tasks, which can't reach the diagnostics themselves. Clones share
+/// the same samples, and every one of them is added to the diagnostics at the start of the next
+/// frame.
+#[derive(Resource, Clone, Default)]
+pub struct ChunkTimings(Arc<Mutex<Vec<(DiagnosticId, Instant, Duration)>>>);
+
+impl ChunkTimings {
+    /// Runs `f` and records how long it took.
+    pub fn time<T>(&self, id: DiagnosticId, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.0.lock().push((id, start, start.elapsed()));
+        result
+    }
+}
+
+/// Unlike measurements added through `Diagnostics`, which keep only one value per frame, this
+/// keeps every sample.
+fn collect_timings(timings: Res<ChunkTimings>, mut store: ResMut<DiagnosticsStore>) {
+    for (id, time, duration) in timings.0.lock().drain(..) {
+        if let Some(diagnostic) = store.get_mut(id).filter(|diagnostic| diagnostic.is_enabled) {
+            diagnostic.add_measurement(DiagnosticMeasurement {
+                time,
+                value: duration.as_secs_f64() * 1000.0,
+            });
+        }
+    }
+}
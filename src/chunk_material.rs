@@ -10,14 +10,25 @@ use bevy::{
     },
 };
 
-pub const ATTRIBUTE_INDEX: MeshVertexAttribute =
-    MeshVertexAttribute::new("Index", 36172836, VertexFormat::Uint32);
+/// Every vertex of a chunk mesh packed into two integers, see [`MeshBuilder`] for the layout.
+///
+/// [`MeshBuilder`]: crate::mesh_builder::MeshBuilder
+pub const ATTRIBUTE_PACKED: MeshVertexAttribute =
+    MeshVertexAttribute::new("Packed", 36172836, VertexFormat::Uint32x2);
 
 #[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
 pub struct ChunkMaterial {
     #[texture(100, dimension = "2d_array")]
     #[sampler(101)]
     pub texture: Handle<Image>,
+    /// How bright sunlit blocks are, which changes over the day.
+    #[uniform(102)]
+    pub daylight: f32,
+    /// The grid cell of the floating origin, which world positions in the shader are relative
+    /// to. Texture variants are picked from absolute block positions, so they don't change as
+    /// the origin moves.
+    #[uniform(103)]
+    pub origin: IVec3,
 }
 
 impl MaterialExtension for ChunkMaterial {
@@ -29,18 +40,30 @@ impl MaterialExtension for ChunkMaterial {
         "shaders/chunk.wgsl".into()
     }
 
+    // The prepass draws the shadow maps, so it needs to unpack the vertices too.
+    fn prepass_vertex_shader() -> ShaderRef {
+        "shaders/chunk.wgsl".into()
+    }
+
+    fn prepass_fragment_shader() -> ShaderRef {
+        "shaders/chunk.wgsl".into()
+    }
+
+    fn deferred_vertex_shader() -> ShaderRef {
+        "shaders/chunk.wgsl".into()
+    }
+
+    fn deferred_fragment_shader() -> ShaderRef {
+        "shaders/chunk.wgsl".into()
+    }
+
     fn specialize(
         _pipeline: &MaterialExtensionPipeline,
         descriptor: &mut RenderPipelineDescriptor,
         layout: &MeshVertexBufferLayout,
         _key: MaterialExtensionKey<Self>,
     ) -> Result<(), SpecializedMeshPipelineError> {
-        let vertex_layout = layout.get_layout(&[
-            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
-            Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
-            Mesh::ATTRIBUTE_UV_0.at_shader_location(2),
-            ATTRIBUTE_INDEX.at_shader_location(3),
-        ])?;
+        let vertex_layout = layout.get_layout(&[ATTRIBUTE_PACKED.at_shader_location(0)])?;
         descriptor.vertex.buffers = vec![vertex_layout];
         Ok(())
     }
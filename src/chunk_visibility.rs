@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use big_space::GridCell;
+use num_traits::ToPrimitive;
+
+use crate::{
+    block::BlockFace,
+    block_definitions::BlockModels,
+    player::PlayerCamera,
+    voxel::{
+        chunk::{iter_blocks, CHUNK_SIZE},
+        chunk_data::ChunkData,
+        chunk_index::ChunkIndex,
+        chunk_pos::ChunkPos,
+    },
+};
+
+/// Which sides of a chunk can be seen through from which other sides, found by flood filling the
+/// space between its opaque blocks.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkVisibility([u8; 6]);
+
+impl ChunkVisibility {
+    /// Every side connected to every other, as for chunks that are empty or not meshed yet.
+    pub const ALL: Self = Self([0b111111; 6]);
+
+    pub fn new(chunk: &ChunkData, models: &BlockModels) -> Self {
+        let index = |x: usize, y: usize, z: usize| x + (y + z * CHUNK_SIZE) * CHUNK_SIZE;
+
+        let mut open = vec![false; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
+        for (x, y, z) in iter_blocks() {
+            open[index(x, y, z)] = !chunk
+                .block(ChunkIndex::new(x, y, z))
+                .is_some_and(|block| block.casts_occlusion(models));
+        }
+
+        let mut visibility = Self([0; 6]);
+        let mut queue = Vec::new();
+        let last = CHUNK_SIZE - 1;
+
+        for (x, y, z) in iter_blocks() {
+            if !open[index(x, y, z)] {
+                continue;
+            }
+
+            // Fill the space this block is part of, noting every side it reaches
+            let mut sides = 0u8;
+            open[index(x, y, z)] = false;
+            queue.push((x, y, z));
+
+            while let Some((x, y, z)) = queue.pop() {
+                for (face, at_side) in [
+                    (BlockFace::Left, x == 0),
+                    (BlockFace::Right, x == last),
+                    (BlockFace::Top, y == last),
+                    (BlockFace::Bottom, y == 0),
+                    (BlockFace::Front, z == last),
+                    (BlockFace::Back, z == 0),
+                ] {
+                    if at_side {
+                        sides |= Self::bit(face);
+                        continue;
+                    }
+
+                    let (x, y, z) = match face {
+                        BlockFace::Left => (x - 1, y, z),
+                        BlockFace::Right => (x + 1, y, z),
+                        BlockFace::Top => (x, y + 1, z),
+                        BlockFace::Bottom => (x, y - 1, z),
+                        BlockFace::Front => (x, y, z + 1),
+                        BlockFace::Back => (x, y, z - 1),
+                    };
+
+                    if open[index(x, y, z)] {
+                        open[index(x, y, z)] = false;
+                        queue.push((x, y, z));
+                    }
+                }
+            }
+
+            for face in BlockFace::ALL {
+                if sides & Self::bit(face) != 0 {
+                    visibility.0[face.to_usize().unwrap()] |= sides;
+                }
+            }
+
+            if visibility == Self::ALL {
+                break;
+            }
+        }
+
+        visibility
+    }
+
+    /// Whether something entering the chunk through one side can come out of the other.
+    pub fn connects(self, from: BlockFace, to: BlockFace) -> bool {
+        self.0[from.to_usize().unwrap()] & Self::bit(to) != 0
+    }
+
+    fn bit(face: BlockFace) -> u8 {
+        1 << face.to_u8().unwrap()
+    }
+}
+
+/// Hides chunks that can't be seen from the camera, such as caves enclosed by rock.
+///
+/// Walks outwards from the chunk of the camera, only ever moving away from it, and only passing
+/// through a chunk between sides that its [`ChunkVisibility`] connects. Chunks without one yet
+/// are treated as open, and nothing is hidden while the camera is outside the loaded chunks.
+pub fn cull_chunks(
+    camera: Query<(&GlobalTransform, &Parent), With<PlayerCamera>>,
+    grid_cells: Query<&GridCell<i32>>,
+    mut chunks: Query<(&ChunkPos, &mut Visibility, Option<&ChunkVisibility>)>,
+) {
+    let Ok((transform, parent)) = camera.get_single() else {
+        return;
+    };
+    let Ok(grid_cell) = grid_cells.get(parent.get()) else {
+        return;
+    };
+
+    let offset = (transform.translation() / CHUNK_SIZE as f32)
+        .floor()
+        .as_ivec3();
+    let start = ChunkPos::from(*grid_cell) + ChunkPos::new(offset.x, offset.y, offset.z);
+
+    let graph: HashMap<ChunkPos, ChunkVisibility> = chunks
+        .iter()
+        .map(|(&pos, _, visibility)| (pos, visibility.copied().unwrap_or(ChunkVisibility::ALL)))
+        .collect();
+
+    // Outside the loaded chunks, such as above the highest ones, there's nowhere to walk from.
+    if !graph.contains_key(&start) {
+        for (_, mut visibility, _) in chunks.iter_mut() {
+            visibility.set_if_neq(Visibility::Inherited);
+        }
+        return;
+    }
+
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+    visited.insert(start);
+    queue.push_back((start, None::<BlockFace>, 0u8));
+
+    while let Some((pos, entered, directions)) = queue.pop_front() {
+        let Some(&visibility) = graph.get(&pos) else {
+            continue;
+        };
+
+        for (face, next) in BlockFace::ALL.into_iter().zip(pos.adjacent()) {
+            // Turning back towards the camera can't reveal anything new.
+            if directions & ChunkVisibility::bit(face.opposite()) != 0 {
+                continue;
+            }
+
+            if entered.is_some_and(|entered| !visibility.connects(entered, face)) {
+                continue;
+            }
+
+            if !visited.insert(next) {
+                continue;
+            }
+
+            let directions = directions | ChunkVisibility::bit(face);
+            queue.push_back((next, Some(face.opposite()), directions));
+        }
+    }
+
+    for (pos, mut visibility, _) in chunks.iter_mut() {
+        let culled = if visited.contains(pos) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        visibility.set_if_neq(culled);
+    }
+}
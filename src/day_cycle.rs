@@ -0,0 +1,308 @@
+use std::f32::consts::TAU;
+
+use bevy::{
+    pbr::{CascadeShadowConfig, CascadeShadowConfigBuilder, ExtendedMaterial},
+    prelude::*,
+};
+use big_space::GridCell;
+
+use crate::{
+    chunk_material::ChunkMaterial,
+    player::{PlayerCamera, RenderDistance},
+    voxel::chunk::CHUNK_SIZE,
+    GameState,
+};
+
+/// Illuminance of the sun and moon when they're high in the sky, in lux.
+const SUN_ILLUMINANCE: f32 = 8000.0;
+const MOON_ILLUMINANCE: f32 = 800.0;
+
+/// How many steps daylight is rounded to before it's given to the chunk materials. Changing it
+/// rebuilds their bind groups, so it only changes a few dozen times through a sunrise rather than
+/// every frame, which is still finer than the light levels it scales.
+const DAYLIGHT_STEPS: f32 = 64.0;
+
+/// Moves the sun and moon across the sky, and colors the sky, fog and ambient light to match the
+/// time of day.
+pub struct DayCyclePlugin;
+
+impl Plugin for DayCyclePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TimeOfDay>()
+            .init_resource::<SkyGradient>()
+            .add_systems(Startup, setup_sky)
+            .add_systems(
+                Update,
+                (
+                    (advance_time.run_if(in_state(GameState::InGame)), update_sky).chain(),
+                    update_shadow_cascades.run_if(resource_changed::<RenderDistance>()),
+                ),
+            );
+    }
+}
+
+/// The time of day as a fraction of a day, where 0 is midnight, 0.25 sunrise, 0.5 noon and 0.75
+/// sunset.
+#[derive(Resource)]
+pub struct TimeOfDay {
+    pub time: f32,
+    /// How long a whole day takes, in seconds.
+    pub day_length: f32,
+    pub paused: bool,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self {
+            time: 0.3,
+            day_length: 20.0 * 60.0,
+            paused: false,
+        }
+    }
+}
+
+impl TimeOfDay {
+    /// The direction pointing towards the sun, which the moon is opposite of.
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = (self.time - 0.25) * TAU;
+        Vec3::new(angle.cos(), angle.sin(), 0.3).normalize()
+    }
+
+    /// The time on a 24 hour clock, as hours and minutes.
+    pub fn clock(&self) -> (u32, u32) {
+        let minutes = (self.time * 24.0 * 60.0) as u32;
+        (minutes / 60 % 24, minutes % 60)
+    }
+}
+
+/// The look of the sky at some time of day.
+#[derive(Debug, Clone, Copy)]
+pub struct SkyKey {
+    pub time: f32,
+    pub sky: Color,
+    pub fog: Color,
+    pub ambient: f32,
+    /// How bright sunlit blocks are, multiplying the light levels baked into chunk meshes.
+    pub daylight: f32,
+}
+
+/// The look of the sky over a day, interpolated between keys sorted by time. The last key wraps
+/// around to the first one.
+#[derive(Resource)]
+pub struct SkyGradient(pub Vec<SkyKey>);
+
+impl Default for SkyGradient {
+    fn default() -> Self {
+        let key = |time, sky, fog, ambient, daylight| SkyKey {
+            time,
+            sky,
+            fog,
+            ambient,
+            daylight,
+        };
+
+        Self(vec![
+            key(
+                0.0,
+                Color::rgb(0.01, 0.02, 0.06),
+                Color::rgb(0.02, 0.03, 0.08),
+                0.4,
+                0.3,
+            ),
+            key(
+                0.2,
+                Color::rgb(0.01, 0.02, 0.06),
+                Color::rgb(0.02, 0.03, 0.08),
+                0.4,
+                0.3,
+            ),
+            key(
+                0.25,
+                Color::rgb(0.8, 0.45, 0.3),
+                Color::rgb(0.85, 0.55, 0.4),
+                0.6,
+                0.6,
+            ),
+            key(
+                0.32,
+                Color::rgb(0.2, 0.5, 0.8),
+                Color::rgb(0.2, 0.5, 0.8),
+                1.0,
+                1.0,
+            ),
+            key(
+                0.68,
+                Color::rgb(0.2, 0.5, 0.8),
+                Color::rgb(0.2, 0.5, 0.8),
+                1.0,
+                1.0,
+            ),
+            key(
+                0.75,
+                Color::rgb(0.85, 0.4, 0.25),
+                Color::rgb(0.9, 0.5, 0.35),
+                0.6,
+                0.6,
+            ),
+            key(
+                0.8,
+                Color::rgb(0.01, 0.02, 0.06),
+                Color::rgb(0.02, 0.03, 0.08),
+                0.4,
+                0.3,
+            ),
+        ])
+    }
+}
+
+impl SkyGradient {
+    pub fn sample(&self, time_of_day: f32) -> SkyKey {
+        let time = time_of_day;
+        let (Some(first), Some(last)) = (self.0.first(), self.0.last()) else {
+            return SkyKey {
+                time,
+                sky: Color::BLACK,
+                fog: Color::BLACK,
+                ambient: 1.0,
+                daylight: 1.0,
+            };
+        };
+
+        let next = self.0.iter().position(|key| key.time > time);
+        let (from, to) = match next {
+            Some(0) | None => (
+                *last,
+                SkyKey {
+                    time: first.time + 1.0,
+                    ..*first
+                },
+            ),
+            Some(next) => (self.0[next - 1], self.0[next]),
+        };
+
+        // Times before the first key are reached by wrapping around from the last one.
+        let time = if time < from.time { time + 1.0 } else { time };
+        let t = ((time - from.time) / (to.time - from.time)).clamp(0.0, 1.0);
+
+        let mix = |a: Color, b: Color| {
+            let (a, b) = (Vec4::from(a.as_rgba_f32()), Vec4::from(b.as_rgba_f32()));
+            Color::from(a.lerp(b, t))
+        };
+
+        SkyKey {
+            time: time_of_day,
+            sky: mix(from.sky, to.sky),
+            fog: mix(from.fog, to.fog),
+            ambient: from.ambient + (to.ambient - from.ambient) * t,
+            daylight: from.daylight + (to.daylight - from.daylight) * t,
+        }
+    }
+}
+
+#[derive(Component)]
+struct Sun;
+
+#[derive(Component)]
+struct Moon;
+
+fn setup_sky(mut commands: Commands) {
+    let light = |illuminance, color, shadows_enabled| {
+        (
+            DirectionalLightBundle {
+                directional_light: DirectionalLight {
+                    illuminance,
+                    color,
+                    shadows_enabled,
+                    ..default()
+                },
+                ..default()
+            },
+            GridCell::<i32>::default(),
+        )
+    };
+
+    // Moonlight is too dim for its shadows to be worth a second set of shadow maps.
+    commands.spawn((
+        light(SUN_ILLUMINANCE, Color::rgb(1.0, 0.96, 0.88), true),
+        Sun,
+    ));
+    commands.spawn((
+        light(MOON_ILLUMINANCE, Color::rgb(0.6, 0.7, 1.0), false),
+        Moon,
+    ));
+}
+
+fn advance_time(time: Res<Time>, mut time_of_day: ResMut<TimeOfDay>) {
+    if time_of_day.paused || time_of_day.day_length <= 0.0 {
+        return;
+    }
+
+    time_of_day.time = (time_of_day.time + time.delta_seconds() / time_of_day.day_length).fract();
+}
+
+fn update_sky(
+    time_of_day: Res<TimeOfDay>,
+    gradient: Res<SkyGradient>,
+    mut clear_color: ResMut<ClearColor>,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut sun: Query<(&mut Transform, &mut DirectionalLight), (With<Sun>, Without<Moon>)>,
+    mut moon: Query<(&mut Transform, &mut DirectionalLight), (With<Moon>, Without<Sun>)>,
+    mut fog: Query<&mut FogSettings, With<PlayerCamera>>,
+    mut materials: ResMut<Assets<ExtendedMaterial<StandardMaterial, ChunkMaterial>>>,
+) {
+    let sky = gradient.sample(time_of_day.time);
+    let to_sun = time_of_day.sun_direction();
+
+    // Each light fades out as it reaches the horizon, rather than lighting the world from below.
+    let height = |direction: Vec3| (direction.y * 4.0).clamp(0.0, 1.0);
+
+    for (direction, (mut transform, mut light), illuminance) in [
+        (to_sun, sun.single_mut(), SUN_ILLUMINANCE),
+        (-to_sun, moon.single_mut(), MOON_ILLUMINANCE),
+    ] {
+        *transform = Transform::default().looking_to(-direction, Vec3::Z);
+        light.illuminance = illuminance * height(direction);
+    }
+
+    clear_color.0 = sky.sky;
+    ambient_light.brightness = sky.ambient;
+
+    for mut fog in fog.iter_mut() {
+        fog.color = sky.fog;
+    }
+
+    // Modifying a material rebuilds its bind group, so only touch the ones that changed.
+    let daylight = (sky.daylight * DAYLIGHT_STEPS).round() / DAYLIGHT_STEPS;
+    let changed: Vec<_> = materials
+        .iter()
+        .filter(|(_, material)| material.extension.daylight != daylight)
+        .map(|(id, _)| id)
+        .collect();
+
+    for id in changed {
+        if let Some(material) = materials.get_mut(id) {
+            material.extension.daylight = daylight;
+        }
+    }
+}
+
+/// Spreads the sun's shadow cascades over the loaded chunks, keeping the first one a chunk wide so
+/// shadows near the player stay sharp.
+fn update_shadow_cascades(
+    render_distance: Res<RenderDistance>,
+    mut sun: Query<&mut CascadeShadowConfig, With<Sun>>,
+) {
+    let Ok(mut cascades) = sun.get_single_mut() else {
+        return;
+    };
+
+    let chunk_size = CHUNK_SIZE as f32;
+    *cascades = CascadeShadowConfigBuilder {
+        num_cascades: 4,
+        minimum_distance: 0.1,
+        maximum_distance: render_distance.0.max(2) as f32 * chunk_size,
+        first_cascade_far_bound: chunk_size,
+        overlap_proportion: 0.2,
+    }
+    .build();
+}
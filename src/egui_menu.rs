@@ -3,13 +3,20 @@ use bevy::{
     prelude::*,
 };
 use bevy_egui::{
-    egui::{ScrollArea, Slider, Ui, Window},
+    egui::{pos2, vec2, Rect, ScrollArea, Sense, Slider, Ui, Window},
     EguiContexts,
 };
 use big_space::{FloatingOriginSettings, GridCell};
+use itertools::Itertools;
 
 use crate::{
-    player::{JumpHeight, MouseSensitivity, MovementSpeed, Player, Reach, RenderDistance},
+    chunk::Mesher,
+    chunk_diagnostics::ChunkDiagnosticsPlugin,
+    day_cycle::TimeOfDay,
+    level::Level,
+    player::{
+        JumpHeight, MouseSensitivity, MovementSpeed, Player, Reach, RenderDistance, SelectedBlock,
+    },
     voxel::chunk_pos::ChunkPos,
     GameState,
 };
@@ -31,6 +38,10 @@ fn render_ui(
     mut mouse_sensitivity: ResMut<MouseSensitivity>,
     mut gizmo_config: ResMut<GizmoConfig>,
     mut reach: ResMut<Reach>,
+    selected_block: Res<SelectedBlock>,
+    mut mesher: ResMut<Mesher>,
+    mut time_of_day: ResMut<TimeOfDay>,
+    level: Res<Level>,
     mut contexts: EguiContexts,
     player: Query<(&GridCell<i32>, &Transform, &GlobalTransform), With<Player>>,
     chunks: Query<&ChunkPos>,
@@ -48,6 +59,15 @@ fn render_ui(
 
         ui.checkbox(&mut gizmo_config.enabled, "Debug Rendering");
 
+        // Changing the mesher rebuilds every chunk, so only touch it when a different one is picked.
+        let mut selected_mesher = *mesher;
+        ui.horizontal(|ui| {
+            ui.label("Mesher:");
+            ui.radio_value(&mut selected_mesher, Mesher::Naive, "Naive");
+            ui.radio_value(&mut selected_mesher, Mesher::Greedy, "Greedy");
+        });
+        mesher.set_if_neq(selected_mesher);
+
         ui.separator();
 
         ui.label(format!(
@@ -62,6 +82,15 @@ fn render_ui(
             pos.x, pos.y, pos.z
         ));
 
+        if let Some(biome) = level
+            .generator()
+            .biome(pos.x.floor() as i64, pos.z.floor() as i64)
+        {
+            ui.label(format!("Biome: {biome}"));
+        }
+
+        ui.label(format!("Selected Block: {:?}", selected_block.0));
+
         ui.label(format!("Loaded Chunks: {}", chunks.iter().len()));
 
         if let Some(value) = diagnostics
@@ -70,9 +99,102 @@ fn render_ui(
         {
             ui.label(format!("FPS: {value:>4.0}"));
         }
+
+        ui.collapsing("Time of Day", |ui| {
+            let (hours, minutes) = time_of_day.clock();
+            ui.label(format!("Time: {hours:02}:{minutes:02}"));
+            ui.add(
+                Slider::new(&mut time_of_day.time, 0.0..=1.0)
+                    .show_value(false)
+                    .text("Time"),
+            );
+            ui.add(
+                Slider::new(&mut time_of_day.day_length, 10.0..=3600.0)
+                    .logarithmic(true)
+                    .suffix(" s")
+                    .text("Day Length"),
+            );
+            ui.checkbox(&mut time_of_day.paused, "Paused");
+        });
+
+        ui.collapsing("Chunk Streaming", |ui| {
+            for (id, name) in ChunkDiagnosticsPlugin::TIMINGS {
+                let Some(diagnostic) = diagnostics.get(id) else {
+                    continue;
+                };
+
+                let values = diagnostic.values().copied().collect_vec();
+                let max = values.iter().copied().fold(0.0, f64::max);
+
+                ui.label(format!(
+                    "{name}: avg {:.2} ms, max {max:.2} ms",
+                    diagnostic.average().unwrap_or_default()
+                ));
+                histogram(ui, &values, max);
+            }
+
+            for (id, name) in [
+                (
+                    ChunkDiagnosticsPlugin::PENDING_GENERATE_TASKS,
+                    "Pending generate tasks",
+                ),
+                (
+                    ChunkDiagnosticsPlugin::PENDING_MESH_TASKS,
+                    "Pending mesh tasks",
+                ),
+                (ChunkDiagnosticsPlugin::QUADS, "Quads"),
+            ] {
+                if let Some(value) = diagnostics.get(id).and_then(|d| d.value()) {
+                    ui.label(format!("{name}: {value}"));
+                }
+            }
+
+            for (id, name) in [
+                (ChunkDiagnosticsPlugin::VERTEX_MEMORY, "Vertex memory"),
+                (ChunkDiagnosticsPlugin::INDEX_MEMORY, "Index memory"),
+            ] {
+                if let Some(value) = diagnostics.get(id).and_then(|d| d.value()) {
+                    ui.label(format!("{name}: {value:.1} MiB"));
+                }
+            }
+        });
     };
 
     Window::new("Debug").show(contexts.ctx_mut(), |ui| {
         ScrollArea::vertical().show(ui, content);
     });
 }
+
+/// Draws how the samples are spread out between zero and the largest one.
+fn histogram(ui: &mut Ui, values: &[f64], max: f64) {
+    const BUCKETS: usize = 24;
+
+    let mut counts = [0; BUCKETS];
+    for &value in values {
+        let bucket = if max > 0.0 {
+            (value / max * BUCKETS as f64) as usize
+        } else {
+            0
+        };
+        counts[bucket.min(BUCKETS - 1)] += 1;
+    }
+
+    let (response, painter) =
+        ui.allocate_painter(vec2(ui.available_width().min(240.0), 32.0), Sense::hover());
+    let rect = response.rect;
+    let highest = counts.iter().copied().max().unwrap_or_default().max(1);
+    let width = rect.width() / BUCKETS as f32;
+
+    for (bucket, &count) in counts.iter().enumerate() {
+        let height = rect.height() * count as f32 / highest as f32;
+        let left = rect.left() + bucket as f32 * width;
+        painter.rect_filled(
+            Rect::from_min_max(
+                pos2(left, rect.bottom() - height),
+                pos2(left + width - 1.0, rect.bottom()),
+            ),
+            0.0,
+            ui.visuals().selection.bg_fill,
+        );
+    }
+}
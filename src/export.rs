@@ -0,0 +1,608 @@
+//! Writes a region of a world to files that other tools can open, straight from the world
+//! database and without opening a window. Run as `export <min> <max> <output> [database]`,
+//! with chunk positions written as `x,y,z`.
+
+use std::{
+    error::Error,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, VertexAttributeValues},
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::{CompressedImageFormats, ImageSampler, ImageType},
+    },
+    tasks::block_on,
+    utils::HashMap,
+};
+use itertools::Itertools;
+use parking_lot::RwLock;
+use rusqlite::Connection;
+use serde_json::json;
+
+use crate::{
+    block::BlockLayer,
+    block_definitions::{BlockDefinitions, BlockModels, BlockTextures, FaceTexture},
+    chunk::{generate_mesh, AdjacentChunks, Mesher},
+    chunk_diagnostics::ChunkTimings,
+    chunk_material::ATTRIBUTE_PACKED,
+    level::{open_database, DATABASE},
+    light::{ChunkLight, Light, LightData, LightEngine, MAX_LIGHT},
+    sky_map::{ChunkSky, SkyMap},
+    voxel::{
+        chunk::{Chunk, CHUNK_SIZE},
+        chunk_data::ChunkData,
+        chunk_pos::ChunkPos,
+        chunk_sections::ChunkSections,
+    },
+    world_generator::{GeneratorPreset, WorldGenerator},
+};
+
+const USAGE: &str = "usage: export <min x,y,z> <max x,y,z> <output.gltf|output.obj> [database]";
+
+/// Size in pixels of a block texture.
+const TEXTURE_SIZE: u32 = 16;
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [min, max, output, rest @ ..] = args else {
+        return Err(USAGE.into());
+    };
+
+    let database = rest.first().map_or(DATABASE, String::as_str);
+
+    export_region(
+        parse_chunk_pos(min)?,
+        parse_chunk_pos(max)?,
+        Path::new(output),
+        Path::new(database),
+    )
+}
+
+fn parse_chunk_pos(text: &str) -> Result<ChunkPos, Box<dyn Error>> {
+    let Some((x, y, z)) = text
+        .split(',')
+        .map(|part| part.trim().parse::<i32>())
+        .collect_tuple()
+    else {
+        return Err(format!("expected a chunk position like 0,3,-2, got {text}\n{USAGE}").into());
+    };
+
+    Ok(ChunkPos::new(x?, y?, z?))
+}
+
+/// Meshes every chunk between `min` and `max`, inclusive, and writes them as a single model.
+/// The format is picked from the extension of `output`, and the texture atlas is written next
+/// to it as a PNG.
+pub fn export_region(
+    min: ChunkPos,
+    max: ChunkPos,
+    output: &Path,
+    database: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let extension = output
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    if extension != "gltf" && extension != "obj" {
+        return Err(format!("can't export to .{extension} files\n{USAGE}").into());
+    }
+
+    let definitions: BlockDefinitions =
+        ron::de::from_bytes(&fs::read("assets/blocks/definitions.blocks.ron")?)?;
+    let textures = BlockTextures::new(&definitions);
+    let models = BlockModels::new(&definitions);
+
+    let atlas = Atlas::new(definitions.texture_names().len() as u32);
+    let atlas_path = output.with_extension("png");
+    atlas
+        .build(&definitions.texture_names())?
+        .try_into_dynamic()?
+        .save(&atlas_path)?;
+
+    let (min, max) = (
+        ChunkPos::new(min.x.min(max.x), min.y.min(max.y), min.z.min(max.z)),
+        ChunkPos::new(min.x.max(max.x), min.y.max(max.y), min.z.max(max.z)),
+    );
+
+    // Chunks around the region are loaded too, so its sides are culled and shaded the same as
+    // in the game.
+    let source = ChunkSource::new(database, models.clone())?;
+    let chunks = source.load(min - ChunkPos::new(1, 1, 1), max + ChunkPos::new(1, 1, 1));
+
+    // Lit the same way as when the chunks are loaded in the game.
+    let skies = source.load_skies(chunks.keys().map(|pos| pos.column()))?;
+    let lights: HashMap<ChunkPos, ChunkLight> = chunks
+        .iter()
+        .map(|(&pos, chunk)| {
+            let light = LightData::new(pos, chunk, &skies[&pos.column()], &models);
+            (pos, Arc::new(RwLock::new(light)))
+        })
+        .collect();
+    let mut engine = LightEngine::new(&chunks, &lights, &skies, &models);
+    for &pos in chunks.keys() {
+        engine.insert_chunk(pos);
+    }
+
+    let mut layers = [
+        ExportMesh::default(),
+        ExportMesh::default(),
+        ExportMesh::default(),
+    ];
+
+    for pos in chunk_range(min, max) {
+        let adjacent = AdjacentChunks {
+            left: chunks.get(&pos.left()).cloned(),
+            right: chunks.get(&pos.right()).cloned(),
+            top: chunks.get(&pos.top()).cloned(),
+            bottom: chunks.get(&pos.bottom()).cloned(),
+            front: chunks.get(&pos.front()).cloned(),
+            back: chunks.get(&pos.back()).cloned(),
+            lods: [0; 6],
+        };
+        let neighborhood = pos.neighborhood().map(|pos| chunks.get(&pos).cloned());
+        let light_neighborhood = pos.neighborhood().map(|pos| lights.get(&pos).cloned());
+
+        // Merged quads would need their texture repeated inside the atlas, so every face is
+        // exported on its own.
+        let build = block_on(generate_mesh(
+            chunks[&pos].clone(),
+            adjacent,
+            neighborhood,
+            light_neighborhood,
+            textures.clone(),
+            models.clone(),
+            Mesher::Naive,
+            0,
+            ChunkSections::ALL,
+            false,
+            ChunkTimings::default(),
+        ));
+
+        let origin = pos.block_pos();
+        let origin = IVec3::new(origin.x as i32, origin.y as i32, origin.z as i32);
+        let offset = pos - min;
+        let offset =
+            Vec3::new(offset.x as f32, offset.y as f32, offset.z as f32) * CHUNK_SIZE as f32;
+
+        for (_, meshes) in build.sections {
+            for (layer, mesh) in [meshes.opaque, meshes.cutout, meshes.translucent]
+                .iter()
+                .enumerate()
+            {
+                layers[layer].append(mesh, origin, offset, &atlas);
+            }
+        }
+    }
+
+    if layers.iter().all(|mesh| mesh.indices.is_empty()) {
+        return Err("there's nothing to export in that region".into());
+    }
+
+    let layers = [
+        (BlockLayer::Opaque, &layers[0]),
+        (BlockLayer::Cutout, &layers[1]),
+        (BlockLayer::Translucent, &layers[2]),
+    ];
+    let atlas_name = atlas_path.file_name().unwrap().to_string_lossy();
+
+    if extension == "gltf" {
+        write_gltf(output, &layers, &atlas_name)
+    } else {
+        write_obj(output, &layers, &atlas_name)
+    }
+}
+
+fn chunk_range(min: ChunkPos, max: ChunkPos) -> impl Iterator<Item = ChunkPos> {
+    (min.x..=max.x)
+        .cartesian_product(min.y..=max.y)
+        .cartesian_product(min.z..=max.z)
+        .map(|((x, y), z)| ChunkPos::new(x, y, z))
+}
+
+/// Reads chunks from the world database, generating the ones that were never saved without
+/// writing them back.
+struct ChunkSource {
+    connection: Connection,
+    generator: Arc<dyn WorldGenerator>,
+    models: BlockModels,
+}
+
+impl ChunkSource {
+    fn new(database: &Path, models: BlockModels) -> Result<Self, Box<dyn Error>> {
+        if !database.exists() {
+            return Err(format!("no world database at {}", database.display()).into());
+        }
+
+        // Worlds from before sky maps were saved get the table for them.
+        let connection = open_database(database)?;
+        let generator = GeneratorPreset::read(&connection)?
+            .unwrap_or_default()
+            .build();
+
+        Ok(Self {
+            connection,
+            generator,
+            models,
+        })
+    }
+
+    fn load(&self, min: ChunkPos, max: ChunkPos) -> HashMap<ChunkPos, Chunk> {
+        chunk_range(min, max)
+            .map(|pos| {
+                let bin_data: rusqlite::Result<Vec<u8>> = self.connection.query_row(
+                    "
+                    SELECT data FROM chunks
+                    WHERE x = ? AND y = ? AND z = ?
+                    ",
+                    (pos.x, pos.y, pos.z),
+                    |row| row.get(0),
+                );
+
+                let chunk_data = bin_data
+                    .ok()
+                    .and_then(|bin_data| ChunkData::deserialize(&bin_data))
+                    .unwrap_or_else(|| self.generator.generate_chunk(pos, &self.models));
+
+                (pos, Arc::new(RwLock::new(chunk_data)))
+            })
+            .collect()
+    }
+
+    fn load_skies(
+        &self,
+        columns: impl Iterator<Item = ChunkPos>,
+    ) -> rusqlite::Result<HashMap<ChunkPos, ChunkSky>> {
+        let mut skies = HashMap::default();
+        for column in columns {
+            if !skies.contains_key(&column) {
+                let sky = SkyMap::load(
+                    &self.connection,
+                    self.generator.as_ref(),
+                    &self.models,
+                    column,
+                )?;
+                skies.insert(column, Arc::new(RwLock::new(sky)));
+            }
+        }
+        Ok(skies)
+    }
+}
+
+/// Every layer of the block array laid out side by side in a single texture.
+struct Atlas {
+    columns: u32,
+    rows: u32,
+}
+
+impl Atlas {
+    fn new(layers: u32) -> Self {
+        let columns = (layers as f32).sqrt().ceil().max(1.0) as u32;
+        let rows = layers.div_ceil(columns).max(1);
+        Self { columns, rows }
+    }
+
+    fn build(&self, names: &[&str]) -> Result<Image, Box<dyn Error>> {
+        let width = self.columns * TEXTURE_SIZE;
+        let height = self.rows * TEXTURE_SIZE;
+        let mut data = vec![0; (width * height * 4) as usize];
+
+        for (layer, name) in names.iter().enumerate() {
+            let path = format!("assets/blocks/{name}.png");
+            let image = Image::from_buffer(
+                &fs::read(&path)?,
+                ImageType::Extension("png"),
+                CompressedImageFormats::NONE,
+                true,
+                ImageSampler::Default,
+            )?;
+
+            let size = image.size();
+            if size != UVec2::splat(TEXTURE_SIZE)
+                || image.data.len() != (size.x * size.y * 4) as usize
+            {
+                return Err(
+                    format!("{path} isn't a {TEXTURE_SIZE}x{TEXTURE_SIZE} RGBA image").into(),
+                );
+            }
+
+            let (column, row) = (layer as u32 % self.columns, layer as u32 / self.columns);
+            for y in 0..TEXTURE_SIZE {
+                let source = (y * TEXTURE_SIZE * 4) as usize;
+                let target =
+                    (((row * TEXTURE_SIZE + y) * width + column * TEXTURE_SIZE) * 4) as usize;
+                let len = (TEXTURE_SIZE * 4) as usize;
+                data[target..target + len].copy_from_slice(&image.data[source..source + len]);
+            }
+        }
+
+        Ok(Image::new(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+        ))
+    }
+
+    /// Moves texture coordinates of a single layer into its place in the atlas.
+    fn uv(&self, layer: u32, uv: Vec2) -> Vec2 {
+        let tile = Vec2::new((layer % self.columns) as f32, (layer / self.columns) as f32);
+        (tile + uv) / Vec2::new(self.columns as f32, self.rows as f32)
+    }
+}
+
+/// The meshes of one block layer, unpacked into plain vertex attributes.
+#[derive(Default)]
+struct ExportMesh {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    /// Ambient occlusion, as a shade to multiply the texture with.
+    colors: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+
+impl ExportMesh {
+    /// Unpacks a chunk mesh the same way `chunk.wgsl` does, picking texture variants from
+    /// block positions relative to `origin`, the first block of the chunk.
+    fn append(&mut self, mesh: &Mesh, origin: IVec3, offset: Vec3, atlas: &Atlas) {
+        let Some(VertexAttributeValues::Uint32x2(vertices)) = mesh.attribute(ATTRIBUTE_PACKED)
+        else {
+            return;
+        };
+        let Some(Indices::U32(indices)) = mesh.indices() else {
+            return;
+        };
+
+        let mut unpacked = HashMap::default();
+
+        for triangle in indices.chunks_exact(3) {
+            let position = |index: u32| {
+                let [x, _] = vertices[index as usize];
+                UVec3::new(x & 0x3ff, x >> 10 & 0x3ff, x >> 20 & 0x3ff).as_vec3() / 16.0
+            };
+
+            // Textures repeat once per block, so coordinates are taken relative to the block
+            // the triangle lies in rather than wrapped per vertex.
+            let center = triangle.iter().map(|&index| position(index)).sum::<Vec3>() / 3.0;
+            let block = center.floor();
+
+            // Faces lie on the side of their block, so the variant is picked from the block
+            // behind them. Plants are in the middle of theirs.
+            let [_, y] = vertices[triangle[0] as usize];
+            let surface = y & 7;
+            let inward = if surface < 6 {
+                surface_normal(surface) / 32.0
+            } else {
+                Vec3::ZERO
+            };
+            let (layer, quarter_turns) = FaceTexture::from_bits(y >> 3 & 0x1fff)
+                .pick(origin + (center - inward).floor().as_ivec3(), surface);
+
+            for &index in triangle {
+                let next = self.positions.len() as u32;
+                let index = *unpacked.entry(index).or_insert_with(|| {
+                    let [x, y] = vertices[index as usize];
+                    let local = position(index) - block;
+                    let occlusion = (x >> 30) as f32 / 3.0;
+                    let light = Light::from_bits(y >> 16);
+                    let brightness = |level: u8| 0.8f32.powi((MAX_LIGHT - level) as i32);
+                    let sun = brightness(light.sun());
+                    let shade = light
+                        .color()
+                        .map(|level| (0.4 + 0.6 * occlusion) * brightness(level).max(sun));
+
+                    self.positions.push((offset + position(index)).to_array());
+                    self.normals.push(surface_normal(surface).to_array());
+                    self.uvs.push(
+                        atlas
+                            .uv(layer, surface_uv(local, surface, quarter_turns))
+                            .to_array(),
+                    );
+                    self.colors.push(shade);
+                    next
+                });
+                self.indices.push(index);
+            }
+        }
+    }
+}
+
+fn surface_normal(surface: u32) -> Vec3 {
+    match surface {
+        0 => Vec3::NEG_X,
+        1 => Vec3::X,
+        3 => Vec3::NEG_Y,
+        4 => Vec3::Z,
+        5 => Vec3::NEG_Z,
+        _ => Vec3::Y,
+    }
+}
+
+fn surface_uv(local: Vec3, surface: u32, quarter_turns: u32) -> Vec2 {
+    let mut uv = match surface {
+        0 | 1 => Vec2::new(local.z, 1.0 - local.y),
+        2 | 3 => Vec2::new(local.z, local.x),
+        _ => Vec2::new(local.x, 1.0 - local.y),
+    };
+
+    for _ in 0..quarter_turns {
+        uv = Vec2::new(1.0 - uv.y, uv.x);
+    }
+
+    uv
+}
+
+fn write_gltf(
+    output: &Path,
+    layers: &[(BlockLayer, &ExportMesh)],
+    atlas_name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let buffer_path = output.with_extension("bin");
+    let buffer_name = buffer_path.file_name().unwrap().to_string_lossy();
+
+    let mut buffer = Vec::<u8>::new();
+    let mut views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut primitives = Vec::new();
+    let mut materials = Vec::new();
+
+    // Adds a tightly packed list of floats or indices, returning its accessor.
+    let mut accessor =
+        |data: &[u8], count: usize, kind: &str, component: u32, bounds: Option<(Vec3, Vec3)>| {
+            views.push(json!({
+                "buffer": 0,
+                "byteOffset": buffer.len(),
+                "byteLength": data.len(),
+            }));
+            buffer.extend_from_slice(data);
+            let mut accessor = json!({
+                "bufferView": views.len() - 1,
+                "componentType": component,
+                "count": count,
+                "type": kind,
+            });
+            if let Some((min, max)) = bounds {
+                accessor["min"] = json!(min.to_array());
+                accessor["max"] = json!(max.to_array());
+            }
+            accessors.push(accessor);
+            accessors.len() - 1
+        };
+
+    for &(layer, mesh) in layers.iter().filter(|(_, mesh)| !mesh.indices.is_empty()) {
+        let bounds = mesh.positions.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), &position| (min.min(position.into()), max.max(position.into())),
+        );
+
+        let count = mesh.positions.len();
+        let position = accessor(
+            &float_bytes(&mesh.positions),
+            count,
+            "VEC3",
+            5126,
+            Some(bounds),
+        );
+        let normal = accessor(&float_bytes(&mesh.normals), count, "VEC3", 5126, None);
+        let uv = accessor(&float_bytes(&mesh.uvs), count, "VEC2", 5126, None);
+        let color = accessor(&float_bytes(&mesh.colors), count, "VEC3", 5126, None);
+        let indices = mesh
+            .indices
+            .iter()
+            .flat_map(|i| i.to_le_bytes())
+            .collect_vec();
+        let indices = accessor(&indices, mesh.indices.len(), "SCALAR", 5125, None);
+
+        let mut material = json!({
+            "pbrMetallicRoughness": {
+                "baseColorTexture": { "index": 0 },
+                "metallicFactor": 0,
+                "roughnessFactor": 1,
+            },
+        });
+        match layer {
+            BlockLayer::Opaque => material["alphaMode"] = json!("OPAQUE"),
+            BlockLayer::Cutout => {
+                material["alphaMode"] = json!("MASK");
+                material["alphaCutoff"] = json!(0.5);
+            }
+            BlockLayer::Translucent => material["alphaMode"] = json!("BLEND"),
+        }
+        materials.push(material);
+
+        primitives.push(json!({
+            "attributes": {
+                "POSITION": position,
+                "NORMAL": normal,
+                "TEXCOORD_0": uv,
+                "COLOR_0": color,
+            },
+            "indices": indices,
+            "material": materials.len() - 1,
+        }));
+    }
+
+    // Nearest filtering keeps the pixel art sharp, as in the game
+    let gltf = json!({
+        "asset": { "version": "2.0", "generator": "export" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0 }],
+        "meshes": [{ "primitives": primitives }],
+        "materials": materials,
+        "textures": [{ "source": 0, "sampler": 0 }],
+        "images": [{ "uri": atlas_name }],
+        "samplers": [{ "magFilter": 9728, "minFilter": 9728 }],
+        "buffers": [{ "uri": buffer_name, "byteLength": buffer.len() }],
+        "bufferViews": views,
+        "accessors": accessors,
+    });
+
+    fs::write(&buffer_path, buffer)?;
+    fs::write(output, serde_json::to_string_pretty(&gltf)?)?;
+    Ok(())
+}
+
+fn float_bytes<const N: usize>(values: &[[f32; N]]) -> Vec<u8> {
+    values
+        .iter()
+        .flatten()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn write_obj(
+    output: &Path,
+    layers: &[(BlockLayer, &ExportMesh)],
+    atlas_name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let material_path: PathBuf = output.with_extension("mtl");
+    let material_name = material_path.file_name().unwrap().to_string_lossy();
+
+    let mut obj = format!("mtllib {material_name}\n");
+    let mut mtl = String::new();
+    let mut first = 1;
+
+    for &(layer, mesh) in layers.iter().filter(|(_, mesh)| !mesh.indices.is_empty()) {
+        let name = format!("{layer:?}").to_lowercase();
+
+        writeln!(mtl, "newmtl {name}\nKd 1 1 1\nmap_Kd {atlas_name}")?;
+        if layer != BlockLayer::Opaque {
+            writeln!(mtl, "map_d {atlas_name}")?;
+        }
+        writeln!(mtl)?;
+
+        writeln!(obj, "o {name}\nusemtl {name}")?;
+        for ([x, y, z], [r, g, b]) in mesh.positions.iter().zip(mesh.colors.iter()) {
+            writeln!(obj, "v {x} {y} {z} {r} {g} {b}")?;
+        }
+        for [u, v] in mesh.uvs.iter() {
+            // OBJ puts the origin of textures at the bottom
+            writeln!(obj, "vt {u} {}", 1.0 - v)?;
+        }
+        for [x, y, z] in mesh.normals.iter() {
+            writeln!(obj, "vn {x} {y} {z}")?;
+        }
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] + first);
+            writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+
+        first += mesh.positions.len() as u32;
+    }
+
+    fs::write(&material_path, mtl)?;
+    fs::write(output, obj)?;
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use rand::Rng;
+
+use crate::{block::Block, voxel::block_pos::BlockPos};
+
+/// How far a feature can reach from where it's placed, in blocks. Features are only looked up in
+/// the chunks next to the one being generated, so this has to stay below the chunk size.
+pub const FEATURE_REACH: i64 = 12;
+
+/// Something larger than a block that's placed on the surface, and may cross chunk borders.
+#[derive(Debug, Clone, Copy)]
+pub enum Feature {
+    /// A log trunk with a ball of leaves on top.
+    Tree { min_height: i64, max_height: i64 },
+    /// A lump of rock half sunk into the ground.
+    Boulder { radius: f64 },
+}
+
+/// A chance of a feature growing in a biome.
+#[derive(Debug, Clone, Copy)]
+pub struct FeatureRule {
+    pub feature: Feature,
+    /// The chance of the feature being placed by each attempt in a chunk, between 0 and 1.
+    pub chance: f64,
+}
+
+impl Feature {
+    /// Calls `place` with the position of every block of the feature, relative to the block above
+    /// the surface it stands on. The same random numbers always give the same blocks.
+    pub fn blocks(self, rng: &mut impl Rng, mut place: impl FnMut(BlockPos, Block)) {
+        match self {
+            Self::Tree {
+                min_height,
+                max_height,
+            } => {
+                let height = rng.gen_range(min_height..=max_height);
+                let radius = rng.gen_range(2..=3);
+
+                // Blocks are only placed where there's room, so the leaves grow around the trunk.
+                for y in 0..height {
+                    place(BlockPos::new(0, y, 0), Block::Log);
+                }
+
+                for y in -radius..=radius {
+                    for x in -radius..=radius {
+                        for z in -radius..=radius {
+                            // Cut the corners off at random so the crowns aren't perfect balls.
+                            let corner = x.abs() + y.abs() + z.abs() > radius + 1;
+                            if corner || (x.abs() == radius && rng.gen_bool(0.3)) {
+                                continue;
+                            }
+                            place(BlockPos::new(x, height + y, z), Block::Leaves);
+                        }
+                    }
+                }
+            }
+            Self::Boulder { radius } => {
+                let center = -radius * 0.5;
+                let reach = radius.ceil() as i64;
+
+                for y in -reach..=reach {
+                    for x in -reach..=reach {
+                        for z in -reach..=reach {
+                            let offset = [x as f64, y as f64 - center, z as f64];
+                            let distance = offset.iter().map(|d| d * d).sum::<f64>().sqrt();
+                            if distance <= radius - rng.gen_range(0.0..0.6) {
+                                place(BlockPos::new(x, y, z), Block::Rock);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use bevy::prelude::*;
+
+use crate::{block::BlockFace, voxel::chunk_sections::SECTION_SIZE};
+
+/// Collects the visible faces of full blocks in a section of a chunk, and merges neighboring faces
+/// in the same plane into larger quads wherever their keys match.
+pub struct GreedyMesher {
+    /// The position of the section in the chunk.
+    origin: UVec3,
+    /// A key for every face of every block, laid out as one slice per face and depth.
+    /// Zero means there is no face.
+    keys: Vec<u64>,
+}
+
+impl GreedyMesher {
+    pub fn new(origin: UVec3) -> Self {
+        Self {
+            origin,
+            keys: vec![0; 6 * SECTION_SIZE * SECTION_SIZE * SECTION_SIZE],
+        }
+    }
+
+    /// Adds a face of the block at the given position in the chunk, which has to be inside the
+    /// section. The key must not be zero.
+    pub fn add_face(&mut self, face: BlockFace, x: usize, y: usize, z: usize, key: u64) {
+        let (x, y, z) = (
+            x - self.origin.x as usize,
+            y - self.origin.y as usize,
+            z - self.origin.z as usize,
+        );
+        let (depth, i, j) = match face {
+            BlockFace::Left | BlockFace::Right => (x, z, y),
+            BlockFace::Top | BlockFace::Bottom => (y, x, z),
+            BlockFace::Front | BlockFace::Back => (z, x, y),
+        };
+        self.keys[Self::index(face, depth, i, j)] = key;
+    }
+
+    /// Calls `emit` for every merged quad with its face, the position of its first block in the
+    /// chunk, its size in blocks and the key of the faces it's made of.
+    pub fn build(mut self, mut emit: impl FnMut(BlockFace, Vec3, Vec3, u64)) {
+        for face in BlockFace::ALL {
+            for depth in 0..SECTION_SIZE {
+                for j in 0..SECTION_SIZE {
+                    let mut i = 0;
+
+                    while i < SECTION_SIZE {
+                        let key = self.keys[Self::index(face, depth, i, j)];
+
+                        if key == 0 {
+                            i += 1;
+                            continue;
+                        }
+
+                        let mut width = 1;
+                        while i + width < SECTION_SIZE
+                            && self.keys[Self::index(face, depth, i + width, j)] == key
+                        {
+                            width += 1;
+                        }
+
+                        let mut height = 1;
+                        while j + height < SECTION_SIZE
+                            && (i..i + width)
+                                .all(|i| self.keys[Self::index(face, depth, i, j + height)] == key)
+                        {
+                            height += 1;
+                        }
+
+                        for j in j..j + height {
+                            for i in i..i + width {
+                                self.keys[Self::index(face, depth, i, j)] = 0;
+                            }
+                        }
+
+                        let (d, u, v) = (depth as f32, i as f32, j as f32);
+                        let (w, h) = (width as f32, height as f32);
+
+                        let (position, size) = match face {
+                            BlockFace::Left | BlockFace::Right => {
+                                (Vec3::new(d, v, u), Vec3::new(1.0, h, w))
+                            }
+                            BlockFace::Top | BlockFace::Bottom => {
+                                (Vec3::new(u, d, v), Vec3::new(w, 1.0, h))
+                            }
+                            BlockFace::Front | BlockFace::Back => {
+                                (Vec3::new(u, v, d), Vec3::new(w, h, 1.0))
+                            }
+                        };
+
+                        emit(face, self.origin.as_vec3() + position, size, key);
+
+                        i += width;
+                    }
+                }
+            }
+        }
+    }
+
+    fn index(face: BlockFace, depth: usize, i: usize, j: usize) -> usize {
+        ((face as usize * SECTION_SIZE + depth) * SECTION_SIZE + j) * SECTION_SIZE + i
+    }
+}
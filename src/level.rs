@@ -1,30 +1,43 @@
-use std::sync::Arc;
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use bevy::{
-    pbr::ExtendedMaterial,
+    diagnostic::Diagnostics,
+    pbr::{ExtendedMaterial, NotShadowCaster},
     prelude::*,
     render::primitives::Aabb,
     tasks::{block_on, AsyncComputeTaskPool, Task},
-    utils::HashMap,
+    utils::{HashMap, HashSet},
 };
 use bevy_xpbd_3d::prelude::*;
-use big_space::GridCell;
+use big_space::{FloatingOrigin, GridCell};
 use futures_lite::future;
 use itertools::Itertools;
 use parking_lot::{Mutex, RwLock};
 use rusqlite::Connection;
 
 use crate::{
-    chunk::{generate_mesh, AdjacentChunks},
+    block_definitions::{BlockModels, BlockTextures},
+    chunk::{generate_mesh, AdjacentChunks, ChunkBuild, ChunkMeshes, Mesher},
+    chunk_diagnostics::{ChunkDiagnosticsPlugin, ChunkTimings},
     chunk_material::ChunkMaterial,
-    level_generator::LevelGenerator,
+    chunk_visibility::{cull_chunks, ChunkVisibility},
+    light::{ChunkLight, LightData, LightEngine},
     player::{Player, RenderDistance},
     plugins::asset_loader::BlockArray,
+    sky_map::{ChunkSky, SkyMap},
     voxel::{
         chunk::{Chunk, CHUNK_SIZE},
         chunk_data::ChunkData,
         chunk_pos::ChunkPos,
+        chunk_sections::{ChunkSections, SECTION_COUNT, SECTION_SIZE},
     },
+    world_generator::{GeneratorPreset, WorldGenerator},
     GameState,
 };
 
@@ -32,51 +45,305 @@ pub struct LevelPlugin;
 
 impl Plugin for LevelPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup_level)
+        app.init_resource::<Mesher>()
+            .add_systems(Startup, setup_level)
             .add_systems(OnEnter(GameState::InGame), setup_material)
             .add_systems(
                 Update,
-                (update_chunks, apply_deferred, build_meshes)
-                    .chain()
+                (
+                    (update_chunks, apply_deferred, build_meshes, cull_chunks).chain(),
+                    measure_chunks,
+                    reload_material,
+                    move_material_origin,
+                    remesh_chunks.run_if(
+                        resource_changed::<BlockTextures>()
+                            .or_else(resource_changed::<BlockModels>())
+                            .or_else(resource_changed::<Mesher>()),
+                    ),
+                )
                     .run_if(in_state(GameState::InGame)),
             );
     }
 }
 
+type ChunkMaterialHandle = Handle<ExtendedMaterial<StandardMaterial, ChunkMaterial>>;
+
 #[derive(Resource)]
-struct ChunkMaterialInstance(Handle<ExtendedMaterial<StandardMaterial, ChunkMaterial>>);
+struct ChunkMaterialInstance {
+    opaque: ChunkMaterialHandle,
+    cutout: ChunkMaterialHandle,
+    translucent: ChunkMaterialHandle,
+}
+
+/// Chunks further away than these distances, in chunks, are meshed at 2×, 4× and 8× lower
+/// resolution.
+const LOD_DISTANCES: [i32; 3] = [6, 12, 18];
+
+/// How many loaded chunks are added to the level each frame at most.
+const MAX_INSERTED_CHUNKS: usize = 8;
+
+fn chunk_lod(center: ChunkPos, pos: ChunkPos) -> u32 {
+    let distance_squared = center.distance_squared(pos);
+    LOD_DISTANCES
+        .iter()
+        .filter(|&&distance| distance_squared > distance * distance)
+        .count() as u32
+}
 
 #[derive(Resource)]
 pub struct Level {
     pub chunks: HashMap<ChunkPos, Chunk>,
-    generator: Arc<LevelGenerator>,
+    /// The light of every loaded chunk, connected across their borders.
+    lights: HashMap<ChunkPos, ChunkLight>,
+    /// The sky map of every column of chunks with any of them loaded, keyed by
+    /// [`ChunkPos::column`].
+    skies: HashMap<ChunkPos, ChunkSky>,
+    /// The level of detail every spawned chunk is meshed at.
+    lods: HashMap<ChunkPos, u32>,
+    generator: Arc<dyn WorldGenerator>,
     database: Arc<Mutex<Connection>>,
+    saves: Arc<Mutex<PendingSaves>>,
+    sky_saves: Arc<Mutex<PendingSaves>>,
+    timings: ChunkTimings,
 }
 
 impl Level {
+    fn lod(&self, pos: ChunkPos) -> u32 {
+        self.lods.get(&pos).copied().unwrap_or_default()
+    }
+
     fn adjacent(&self, pos: ChunkPos) -> AdjacentChunks {
+        let side = |pos: ChunkPos| self.chunks.get(&pos).cloned();
+
         AdjacentChunks {
-            left: self.chunks.get(&pos.left()).cloned(),
-            right: self.chunks.get(&pos.right()).cloned(),
-            top: self.chunks.get(&pos.top()).cloned(),
-            bottom: self.chunks.get(&pos.bottom()).cloned(),
-            front: self.chunks.get(&pos.front()).cloned(),
-            back: self.chunks.get(&pos.back()).cloned(),
+            left: side(pos.left()),
+            right: side(pos.right()),
+            top: side(pos.top()),
+            bottom: side(pos.bottom()),
+            front: side(pos.front()),
+            back: side(pos.back()),
+            lods: [
+                pos.left(),
+                pos.right(),
+                pos.top(),
+                pos.bottom(),
+                pos.front(),
+                pos.back(),
+            ]
+            .map(|pos| self.lod(pos)),
+        }
+    }
+
+    fn neighborhood(&self, pos: ChunkPos) -> [Option<Chunk>; 27] {
+        pos.neighborhood().map(|pos| self.chunks.get(&pos).cloned())
+    }
+
+    fn light_neighborhood(&self, pos: ChunkPos) -> [Option<ChunkLight>; 27] {
+        pos.neighborhood().map(|pos| self.lights.get(&pos).cloned())
+    }
+
+    pub fn generator(&self) -> &dyn WorldGenerator {
+        self.generator.as_ref()
+    }
+
+    /// Spreads light between the loaded chunks, for updating it after they change.
+    pub fn light_engine<'a>(&'a self, models: &'a BlockModels) -> LightEngine<'a> {
+        LightEngine::new(&self.chunks, &self.lights, &self.skies, models)
+    }
+
+    /// Writes the current contents of a loaded chunk back to the database.
+    pub fn save_chunk(&self, pos: ChunkPos) {
+        let Some(chunk) = self.chunks.get(&pos).cloned() else {
+            return;
+        };
+
+        let version = self.saves.lock().queue(pos);
+        let saves = self.saves.clone();
+        let db = self.database.clone();
+        let timings = self.timings.clone();
+        let thread_pool = AsyncComputeTaskPool::get();
+
+        thread_pool
+            .spawn(async move {
+                // Holding the database keeps a newer save from being written between checking
+                // this one and writing it.
+                let db = db.lock();
+                if !saves.lock().finish(pos, version) {
+                    return;
+                }
+
+                let bin_data = chunk.read().serialize();
+                timings.time(ChunkDiagnosticsPlugin::DATABASE_WRITE, || {
+                    db.execute(
+                        "
+                        INSERT OR REPLACE INTO chunks (x, y, z, data)
+                        VALUES (?1, ?2, ?3, ?4)
+                        ",
+                        (pos.x, pos.y, pos.z, bin_data),
+                    )
+                    .unwrap();
+                });
+            })
+            .detach();
+    }
+
+    /// Writes the sky map of a column of chunks back to the database, after its blocks changed.
+    pub fn save_sky(&self, column: ChunkPos) {
+        let Some(sky) = self.skies.get(&column) else {
+            return;
+        };
+
+        let sky = sky.read().clone();
+        let version = self.sky_saves.lock().queue(column);
+        let saves = self.sky_saves.clone();
+        let db = self.database.clone();
+        let thread_pool = AsyncComputeTaskPool::get();
+
+        thread_pool
+            .spawn(async move {
+                let db = db.lock();
+                if saves.lock().finish(column, version) {
+                    sky.save(&db, column).unwrap();
+                }
+            })
+            .detach();
+    }
+}
+
+/// The newest save of every chunk, or sky map of a column, that's waiting to be written. Saves are
+/// written by tasks that can finish in any order, so the ones that were overtaken by a newer save
+/// of the same chunk are dropped instead of writing older contents over it.
+#[derive(Default)]
+struct PendingSaves {
+    versions: HashMap<ChunkPos, u64>,
+    next_version: u64,
+}
+
+impl PendingSaves {
+    /// Makes a new save of a chunk the one to write, returning its version.
+    fn queue(&mut self, pos: ChunkPos) -> u64 {
+        self.next_version += 1;
+        self.versions.insert(pos, self.next_version);
+        self.next_version
+    }
+
+    /// Whether a save is still the newest of its chunk, in which case it's no longer pending.
+    fn finish(&mut self, pos: ChunkPos, version: u64) -> bool {
+        let newest = self.versions.get(&pos) == Some(&version);
+        if newest {
+            self.versions.remove(&pos);
         }
+        newest
     }
 }
 
+/// Loads or generates a chunk and lights it on its own, along with the sky map of its column if
+/// that wasn't loaded yet. Dropping the task, such as when its chunk
+/// is despawned, cancels it, and a generator that's already running stops before saving anything.
 #[derive(Component)]
-struct GenerateChunkTask(Task<Chunk>);
+struct GenerateChunkTask {
+    task: Task<Option<(Chunk, ChunkLight, ChunkSky)>>,
+    cancelled: Arc<AtomicBool>,
+}
 
-#[derive(Component)]
-struct BuildMeshTask(Task<(Mesh, Option<Collider>)>);
+impl Drop for GenerateChunkTask {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The mesh tasks of a chunk that are still running. Every task gets a higher version than the
+/// ones before it, and each part of a result is only shown if it's newer than what's in place.
+#[derive(Component, Default)]
+struct BuildMeshTasks {
+    tasks: Vec<(u64, Dirty, Task<ChunkBuild>)>,
+    next_version: u64,
+    section_versions: [u64; SECTION_COUNT],
+    blocks_version: u64,
+}
 
+impl BuildMeshTasks {
+    /// Whether a task still rebuilds anything that's older than its result.
+    fn is_current(&self, version: u64, dirty: Dirty) -> bool {
+        dirty
+            .sections
+            .iter()
+            .any(|section| self.section_versions[section] < version)
+            || dirty.blocks && self.blocks_version < version
+    }
+}
+
+/// The parts of a chunk that have to be rebuilt.
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+pub struct Dirty {
+    pub sections: ChunkSections,
+    /// Whether the blocks of the chunk itself changed, or ones its collider connects to across
+    /// its sides, which rebuilds its collider and visibility.
+    pub blocks: bool,
+}
+
+impl Dirty {
+    pub const ALL: Self = Self {
+        sections: ChunkSections::ALL,
+        blocks: true,
+    };
+
+    /// Only the meshes of some sections, for changes around the chunk rather than in it.
+    pub fn sections(sections: ChunkSections) -> Self {
+        Self {
+            sections,
+            blocks: false,
+        }
+    }
+
+    /// Adds to whatever is already marked on the chunk, which inserting the component would
+    /// replace.
+    pub fn mark(self, commands: &mut Commands, entity: Entity) {
+        commands.add(move |world: &mut World| {
+            let Some(mut entity) = world.get_entity_mut(entity) else {
+                return;
+            };
+
+            if let Some(mut dirty) = entity.get_mut::<Dirty>() {
+                dirty.sections |= self.sections;
+                dirty.blocks |= self.blocks;
+            } else {
+                entity.insert(self);
+            }
+        });
+    }
+
+    /// Removes what a mesh task was started for, keeping anything marked since.
+    fn clear(self, commands: &mut Commands, entity: Entity) {
+        commands.add(move |world: &mut World| {
+            let Some(mut entity) = world.get_entity_mut(entity) else {
+                return;
+            };
+
+            let Some(mut dirty) = entity.get_mut::<Dirty>() else {
+                return;
+            };
+
+            dirty.sections = dirty.sections - self.sections;
+            dirty.blocks &= !self.blocks;
+
+            if dirty.sections.is_empty() && !dirty.blocks {
+                entity.remove::<Dirty>();
+            }
+        });
+    }
+}
+
+/// A mesh of one section of a chunk, spawned as a child of the chunk.
 #[derive(Component)]
-pub struct Dirty;
+struct SectionMesh(usize);
 
-fn setup_level(mut commands: Commands) {
-    let conn = Connection::open("chunks.sqlite").unwrap();
+/// The database of the world the game plays in.
+pub const DATABASE: &str = "chunks.sqlite";
+
+/// Opens a world database, creating its tables if it's new.
+pub fn open_database(path: impl AsRef<Path>) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
 
     conn.execute(
         "
@@ -89,13 +356,40 @@ fn setup_level(mut commands: Commands) {
         )
         ",
         (),
-    )
-    .unwrap();
+    )?;
+
+    conn.execute(
+        "
+        CREATE TABLE IF NOT EXISTS sky_maps (
+            x INTEGER NOT NULL,
+            z INTEGER NOT NULL,
+            data BLOB NOT NULL,
+            UNIQUE(x, z)
+        )
+        ",
+        (),
+    )?;
+
+    Ok(conn)
+}
+
+fn setup_level(mut commands: Commands, timings: Res<ChunkTimings>) {
+    let conn = open_database(DATABASE).unwrap();
+
+    let generator = GeneratorPreset::load_or_create(&conn)
+        .unwrap_or_else(|error| panic!("can't start the world: {error}"))
+        .build();
 
     commands.insert_resource(Level {
         chunks: HashMap::default(),
-        generator: Arc::default(),
+        lights: HashMap::default(),
+        skies: HashMap::default(),
+        lods: HashMap::default(),
+        generator,
         database: Arc::new(Mutex::new(conn)),
+        saves: Arc::default(),
+        sky_saves: Arc::default(),
+        timings: timings.clone(),
     });
 }
 
@@ -104,23 +398,90 @@ fn setup_material(
     block_array: Res<BlockArray>,
     mut materials: ResMut<Assets<ExtendedMaterial<StandardMaterial, ChunkMaterial>>>,
 ) {
-    let material_handle = materials.add(ExtendedMaterial {
-        base: StandardMaterial {
-            perceptual_roughness: 1.0,
-            ..default()
-        },
-        extension: ChunkMaterial {
-            texture: block_array.clone(),
-        },
+    let mut material = |alpha_mode| {
+        materials.add(ExtendedMaterial {
+            base: StandardMaterial {
+                perceptual_roughness: 1.0,
+                alpha_mode,
+                ..default()
+            },
+            extension: ChunkMaterial {
+                texture: block_array.clone(),
+                daylight: 1.0,
+                origin: IVec3::ZERO,
+            },
+        })
+    };
+
+    commands.insert_resource(ChunkMaterialInstance {
+        opaque: material(AlphaMode::Opaque),
+        cutout: material(AlphaMode::Mask(0.5)),
+        translucent: material(AlphaMode::Blend),
     });
-    commands.insert_resource(ChunkMaterialInstance(material_handle));
+}
+
+/// Materials only pick up a changed texture when they're modified themselves.
+fn reload_material(
+    mut events: EventReader<AssetEvent<Image>>,
+    block_array: Res<BlockArray>,
+    instance: Res<ChunkMaterialInstance>,
+    mut materials: ResMut<Assets<ExtendedMaterial<StandardMaterial, ChunkMaterial>>>,
+) {
+    let modified = events
+        .read()
+        .any(|event| event.is_modified(block_array.id()));
+
+    if !modified {
+        return;
+    }
+
+    for handle in [&instance.opaque, &instance.cutout, &instance.translucent] {
+        materials.get_mut(handle);
+    }
+}
+
+/// Keeps the origin the shader picks texture variants from in step with the floating origin.
+fn move_material_origin(
+    origin: Query<&GridCell<i32>, (With<FloatingOrigin>, Changed<GridCell<i32>>)>,
+    instance: Res<ChunkMaterialInstance>,
+    mut materials: ResMut<Assets<ExtendedMaterial<StandardMaterial, ChunkMaterial>>>,
+) {
+    let Ok(cell) = origin.get_single() else {
+        return;
+    };
+
+    for handle in [&instance.opaque, &instance.cutout, &instance.translucent] {
+        if let Some(material) = materials.get_mut(handle) {
+            material.extension.origin = IVec3::new(cell.x, cell.y, cell.z);
+        }
+    }
+}
+
+/// Texture layers are baked into the meshes, so they have to be rebuilt when those change,
+/// as well as when switching to a different mesher. New models also change the colliders and
+/// visibility of the chunks.
+fn remesh_chunks(
+    mut commands: Commands,
+    models: Res<BlockModels>,
+    chunks: Query<Entity, (With<ChunkPos>, Without<GenerateChunkTask>)>,
+) {
+    let dirty = if models.is_changed() {
+        Dirty::ALL
+    } else {
+        Dirty::sections(ChunkSections::ALL)
+    };
+
+    for entity in chunks.iter() {
+        dirty.mark(&mut commands, entity);
+    }
 }
 
 fn update_chunks(
     mut commands: Commands,
     mut level: ResMut<Level>,
+    models: Res<BlockModels>,
     render_distance: Res<RenderDistance>,
-    material: Res<ChunkMaterialInstance>,
+    mut last_center: Local<Option<ChunkPos>>,
     player: Query<(&GridCell<i32>, &Transform), With<Player>>,
     mut chunks: Query<(&ChunkPos, Entity, Option<&mut GenerateChunkTask>)>,
 ) {
@@ -148,6 +509,27 @@ fn update_chunks(
         }
     }
 
+    // Update levels of detail, remeshing the sides of the neighbors of changed chunks as they're
+    // culled against them at the coarser of both levels
+    if *last_center != Some(center) {
+        *last_center = Some(center);
+
+        for (&pos, &entity) in chunk_entities.iter() {
+            let lod = chunk_lod(center, pos);
+            if level.lods.insert(pos, lod) == Some(lod) {
+                continue;
+            }
+
+            Dirty::ALL.mark(&mut commands, entity);
+            for adjacent in pos.adjacent() {
+                if let Some(&entity) = chunk_entities.get(&adjacent) {
+                    let sections = ChunkSections::bordering(pos - adjacent);
+                    Dirty::sections(sections).mark(&mut commands, entity);
+                }
+            }
+        }
+    }
+
     let mut inserted_chunks = Vec::new();
 
     // Insert chunk
@@ -155,34 +537,78 @@ fn update_chunks(
         .iter()
         .filter(|chunk| visible_chunks.contains(chunk.0))
     {
+        // Connecting their light happens on the main thread, so the rest wait for later frames
+        if inserted_chunks.len() >= MAX_INSERTED_CHUNKS {
+            break;
+        }
+
         let Some(task) = chunk_tasks.get_mut(&pos) else {
             continue;
         };
 
-        let Some(chunk) = block_on(future::poll_once(&mut task.0)) else {
+        let Some(chunk) = block_on(future::poll_once(&mut task.task)) else {
             continue;
         };
 
+        // Only cancelled tasks come back empty, which can't be polled anymore.
+        let Some((chunk, mut light, sky)) = chunk else {
+            continue;
+        };
+
+        // Another chunk of the column may have brought in its sky map and changed it since, in
+        // which case the chunk is lit again with that one.
+        let level_sky = level
+            .skies
+            .entry(pos.column())
+            .or_insert(sky.clone())
+            .clone();
+        if !Arc::ptr_eq(&sky, &level_sky) && *sky.read() != *level_sky.read() {
+            light = Arc::new(RwLock::new(LightData::new(
+                pos, &chunk, &level_sky, &models,
+            )));
+        }
+
         level.chunks.insert(pos, chunk);
+        level.lights.insert(pos, light);
         inserted_chunks.push(pos);
 
-        commands
-            .entity(entity)
-            .remove::<GenerateChunkTask>()
-            .insert((Dirty, material.0.clone()));
+        commands.entity(entity).remove::<GenerateChunkTask>();
+        Dirty::ALL.mark(&mut commands, entity);
     }
 
-    // Regenerate chunks
-    for pos in inserted_chunks
-        .iter()
-        .flat_map(|pos| pos.adjacent())
-        .unique()
-    {
-        let Some(&entity) = chunk_entities.get(&pos) else {
-            continue;
-        };
+    // Regenerate the sections of neighboring chunks facing the new ones, including diagonal ones
+    // since ambient occlusion reaches across corners. The colliders of the ones sharing a side
+    // are rebuilt too, as fences on it connect to the new chunk.
+    for &inserted in inserted_chunks.iter() {
+        for pos in inserted.neighborhood() {
+            let Some(&entity) = chunk_entities.get(&pos) else {
+                continue;
+            };
+
+            let offset = inserted - pos;
+            let dirty = Dirty {
+                sections: ChunkSections::bordering(offset),
+                blocks: offset.x.abs() + offset.y.abs() + offset.z.abs() == 1,
+            };
+            dirty.mark(&mut commands, entity);
+        }
+    }
+
+    // Let light flow between the new chunks and their neighbors, which can reach further than
+    // the sections facing them
+    let mut light_engine = level.light_engine(&models);
+    for &inserted in inserted_chunks.iter() {
+        level
+            .timings
+            .time(ChunkDiagnosticsPlugin::INSERT_LIGHT, || {
+                light_engine.insert_chunk(inserted)
+            });
+    }
 
-        commands.entity(entity).insert(Dirty);
+    for (pos, sections) in light_engine.changed() {
+        if let Some(&entity) = chunk_entities.get(&pos) {
+            Dirty::sections(sections).mark(&mut commands, entity);
+        }
     }
 
     // Spawn chunks
@@ -192,37 +618,72 @@ fn update_chunks(
         .take(25 - chunk_tasks.len())
     {
         let generator = level.generator.clone();
+        let models = models.clone();
         let db = level.database.clone();
+        let sky = level.skies.get(&pos.column()).cloned();
+        let timings = level.timings.clone();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let task_cancelled = cancelled.clone();
         let task = thread_pool.spawn(async move {
-            let bin_data: rusqlite::Result<Vec<u8>> = db.lock().query_row(
-                "
-                SELECT data FROM chunks
-                WHERE x = ? AND y = ? AND z = ?
-                ",
-                (pos.x, pos.y, pos.z),
-                |row| row.get(0),
-            );
+            let bin_data: rusqlite::Result<Vec<u8>> =
+                timings.time(ChunkDiagnosticsPlugin::DATABASE_READ, || {
+                    db.lock().query_row(
+                        "
+                        SELECT data FROM chunks
+                        WHERE x = ? AND y = ? AND z = ?
+                        ",
+                        (pos.x, pos.y, pos.z),
+                        |row| row.get(0),
+                    )
+                });
 
             let chunk_data = if let Ok(bin_data) = bin_data {
                 ChunkData::deserialize(&bin_data).unwrap()
             } else {
-                let chunk_data = generator.generate_chunk(pos);
+                if task_cancelled.load(Ordering::Relaxed) {
+                    return None;
+                }
+
+                let chunk_data = timings.time(ChunkDiagnosticsPlugin::GENERATE_CHUNK, || {
+                    generator.generate_chunk(pos, &models)
+                });
+
+                if task_cancelled.load(Ordering::Relaxed) {
+                    return None;
+                }
+
+                // A cancelled task for the same chunk may have saved it in the meantime.
                 let bin_data = chunk_data.serialize();
-                db.lock()
-                    .execute(
-                        "
-                    INSERT INTO chunks (x, y, z, data)
-                    VALUES (?1, ?2, ?3, ?4)
-                    ",
-                        (pos.x, pos.y, pos.z, bin_data),
-                    )
-                    .unwrap();
+                timings.time(ChunkDiagnosticsPlugin::DATABASE_WRITE, || {
+                    db.lock()
+                        .execute(
+                            "
+                            INSERT OR IGNORE INTO chunks (x, y, z, data)
+                            VALUES (?1, ?2, ?3, ?4)
+                            ",
+                            (pos.x, pos.y, pos.z, bin_data),
+                        )
+                        .unwrap();
+                });
                 chunk_data
             };
 
-            Arc::new(RwLock::new(chunk_data))
+            let sky = match sky {
+                Some(sky) => sky,
+                None => {
+                    let sky = SkyMap::load(&db.lock(), generator.as_ref(), &models, pos.column())
+                        .unwrap();
+                    Arc::new(RwLock::new(sky))
+                }
+            };
+
+            let chunk = Arc::new(RwLock::new(chunk_data));
+            let light = Arc::new(RwLock::new(LightData::new(pos, &chunk, &sky, &models)));
+            Some((chunk, light, sky))
         });
 
+        level.lods.insert(pos, chunk_lod(center, pos));
+
         commands.spawn((
             pos,
             SpatialBundle::default(),
@@ -230,62 +691,270 @@ fn update_chunks(
             RigidBody::Static,
             Friction::new(0.0),
             Restitution::new(0.0),
-            GenerateChunkTask(task),
+            GenerateChunkTask { task, cancelled },
         ));
     }
 
     // Despawn chunks
+    let mut removed_lights = Vec::new();
     for (pos, &entity) in chunk_entities
         .iter()
         .filter(|chunk| !visible_chunks.contains(chunk.0))
     {
         level.chunks.remove(pos);
-        commands.entity(entity).despawn();
+        removed_lights.extend(level.lights.remove(pos).map(|light| (*pos, light)));
+        level.lods.remove(pos);
+        commands.entity(entity).despawn_recursive();
+    }
+
+    // Take back the light the removed chunks spread into the ones still loaded
+    let removed_any = !removed_lights.is_empty();
+    let mut light_engine = level.light_engine(&models);
+    for (pos, light) in removed_lights {
+        light_engine.remove_chunk(pos, &light.read());
+    }
+
+    for (pos, sections) in light_engine.changed() {
+        if !level.chunks.contains_key(&pos) {
+            continue;
+        }
+        if let Some(&entity) = chunk_entities.get(&pos) {
+            Dirty::sections(sections).mark(&mut commands, entity);
+        }
+    }
+
+    // Sky maps are saved as soon as they change, so the ones of columns that were left can go
+    if removed_any {
+        let columns: HashSet<ChunkPos> = level.chunks.keys().map(|pos| pos.column()).collect();
+        level.skies.retain(|column, _| columns.contains(column));
     }
 }
 
 fn build_meshes(
     mut commands: Commands,
     level: Res<Level>,
-    dirty: Query<(Entity, &ChunkPos), With<Dirty>>,
-    mut pending: Query<(Entity, &mut BuildMeshTask)>,
+    textures: Res<BlockTextures>,
+    models: Res<BlockModels>,
+    mesher: Res<Mesher>,
+    material: Res<ChunkMaterialInstance>,
+    mut chunks: Query<
+        (
+            Entity,
+            &ChunkPos,
+            Option<&mut BuildMeshTasks>,
+            Option<&Dirty>,
+            Option<&Children>,
+        ),
+        Or<(With<Dirty>, With<BuildMeshTasks>)>,
+    >,
+    section_meshes: Query<&SectionMesh>,
     mut meshes: ResMut<Assets<Mesh>>,
 ) {
     let thread_pool = AsyncComputeTaskPool::get();
 
-    // Insert mesh
-    for (entity, mut task) in pending.iter_mut() {
-        let Some((mesh, collider)) = block_on(future::poll_once(&mut task.0)) else {
-            continue;
+    for (entity, &pos, tasks, dirty, children) in chunks.iter_mut() {
+        let mut created = None;
+        let tasks = match tasks {
+            Some(tasks) => tasks.into_inner(),
+            None => created.insert(BuildMeshTasks::default()),
         };
 
-        let mut entity = commands.entity(entity);
+        // Insert meshes, newest first so that every part is only replaced once
+        let mut finished = Vec::new();
+        tasks.tasks.retain_mut(|(version, _, task)| {
+            let Some(result) = block_on(future::poll_once(task)) else {
+                return true;
+            };
+            finished.push((*version, result));
+            false
+        });
+        finished.sort_by_key(|(version, _)| std::cmp::Reverse(*version));
+
+        for (version, build) in finished {
+            let sections = build
+                .sections
+                .into_iter()
+                .filter(|&(section, _)| tasks.section_versions[section] < version)
+                .collect_vec();
+
+            for &(section, _) in sections.iter() {
+                tasks.section_versions[section] = version;
+            }
+
+            let newer_blocks = tasks.blocks_version < version;
+            let collider = build.collider.filter(|_| newer_blocks);
+            let visibility = build.visibility.filter(|_| newer_blocks);
+
+            if collider.is_some() {
+                tasks.blocks_version = version;
+            }
+
+            insert_meshes(
+                &mut commands,
+                entity,
+                children.map_or(&[][..], |children| &children[..]),
+                &section_meshes,
+                sections,
+                collider,
+                visibility,
+                &material,
+                &mut meshes,
+            );
+        }
+
+        // Tasks older than everything they'd replace are stale, and dropping them cancels any
+        // that haven't started yet.
+        let mut pending = std::mem::take(&mut tasks.tasks);
+        pending.retain(|(version, dirty, _)| tasks.is_current(*version, *dirty));
+        tasks.tasks = pending;
+
+        // Spawn mesh task
+        if let Some((&dirty, chunk)) = dirty.zip(level.chunks.get(&pos).cloned()) {
+            let adjacent = level.adjacent(pos);
+            let neighborhood = level.neighborhood(pos);
+            let lights = level.light_neighborhood(pos);
+            let gen = generate_mesh(
+                chunk,
+                adjacent,
+                neighborhood,
+                lights,
+                textures.clone(),
+                models.clone(),
+                *mesher,
+                level.lod(pos),
+                dirty.sections,
+                dirty.blocks,
+                level.timings.clone(),
+            );
 
-        if let Some(collider) = collider {
-            entity.insert(collider);
-        } else {
-            entity.remove::<Collider>();
+            tasks.next_version += 1;
+            tasks
+                .tasks
+                .push((tasks.next_version, dirty, thread_pool.spawn(gen)));
+            dirty.clear(&mut commands, entity);
         }
 
-        entity
-            .remove::<BuildMeshTask>()
-            .remove::<Aabb>()
-            .insert(meshes.add(mesh));
+        let empty = tasks.tasks.is_empty();
+        match created {
+            Some(tasks) if !empty => {
+                commands.entity(entity).insert(tasks);
+            }
+            None if empty => {
+                commands.entity(entity).remove::<BuildMeshTasks>();
+            }
+            _ => {}
+        }
     }
+}
 
-    // Spawn mesh task
-    for (entity, &pos) in dirty.iter() {
-        let Some(chunk) = level.chunks.get(&pos).cloned() else {
-            continue;
-        };
+fn insert_meshes(
+    commands: &mut Commands,
+    entity: Entity,
+    children: &[Entity],
+    section_meshes: &Query<&SectionMesh>,
+    sections: Vec<(usize, ChunkMeshes)>,
+    collider: Option<Option<Collider>>,
+    visibility: Option<ChunkVisibility>,
+    material: &ChunkMaterialInstance,
+    meshes: &mut Assets<Mesh>,
+) {
+    match collider {
+        Some(Some(collider)) => {
+            commands.entity(entity).insert(collider);
+        }
+        Some(None) => {
+            commands.entity(entity).remove::<Collider>();
+        }
+        None => {}
+    }
 
-        let adjacent = level.adjacent(pos);
-        let gen = generate_mesh(chunk, adjacent);
-        let task = thread_pool.spawn(gen);
+    if let Some(visibility) = visibility {
+        commands.entity(entity).insert(visibility);
+    }
+
+    for &child in children {
+        let replaced = section_meshes
+            .get(child)
+            .is_ok_and(|mesh| sections.iter().any(|&(section, _)| section == mesh.0));
 
-        commands
-            .entity(entity)
-            .remove::<Dirty>()
-            .insert(BuildMeshTask(task));
+        if replaced {
+            commands.entity(child).despawn_recursive();
+        }
     }
+
+    // Each render pass gets its own child entity, since an entity can only have one material.
+    commands.entity(entity).with_children(|parent| {
+        for (section, chunk_meshes) in sections {
+            let min = ChunkSections::origin(section).as_vec3();
+
+            for (mesh, handle, casts_shadows) in [
+                (chunk_meshes.opaque, &material.opaque, true),
+                (chunk_meshes.cutout, &material.cutout, true),
+                (chunk_meshes.translucent, &material.translucent, false),
+            ] {
+                if mesh.count_vertices() == 0 {
+                    continue;
+                }
+
+                // Bounds can't be computed from packed vertices, so cover the whole section.
+                let mut section_mesh = parent.spawn((
+                    MaterialMeshBundle {
+                        mesh: meshes.add(mesh),
+                        material: handle.clone(),
+                        ..default()
+                    },
+                    Aabb::from_min_max(min, min + Vec3::splat(SECTION_SIZE as f32)),
+                    SectionMesh(section),
+                ));
+
+                // Water and ice would shade everything under them.
+                if !casts_shadows {
+                    section_mesh.insert(NotShadowCaster);
+                }
+            }
+        }
+    });
+}
+
+/// Reports how much work is queued up and how much memory the chunk meshes take.
+fn measure_chunks(
+    mut diagnostics: Diagnostics,
+    generate_tasks: Query<(), With<GenerateChunkTask>>,
+    mesh_tasks: Query<&BuildMeshTasks>,
+    section_meshes: Query<&Handle<Mesh>, With<SectionMesh>>,
+    meshes: Res<Assets<Mesh>>,
+) {
+    diagnostics.add_measurement(ChunkDiagnosticsPlugin::PENDING_GENERATE_TASKS, || {
+        generate_tasks.iter().len() as f64
+    });
+    diagnostics.add_measurement(ChunkDiagnosticsPlugin::PENDING_MESH_TASKS, || {
+        mesh_tasks
+            .iter()
+            .map(|tasks| tasks.tasks.len())
+            .sum::<usize>() as f64
+    });
+
+    let (vertices, indices, quads) = section_meshes
+        .iter()
+        .filter_map(|handle| meshes.get(handle))
+        .fold((0, 0, 0), |(vertices, indices, quads), mesh| {
+            let vertex_bytes: usize = mesh
+                .attributes()
+                .map(|(_, values)| values.get_bytes().len())
+                .sum();
+            let index_count = mesh.indices().map_or(0, |indices| indices.len());
+            (
+                vertices + vertex_bytes,
+                indices + index_count * std::mem::size_of::<u32>(),
+                // Every quad is two triangles
+                quads + index_count / 6,
+            )
+        });
+
+    let mebibytes = |bytes: usize| bytes as f64 / (1024.0 * 1024.0);
+    diagnostics.add_measurement(ChunkDiagnosticsPlugin::VERTEX_MEMORY, || {
+        mebibytes(vertices)
+    });
+    diagnostics.add_measurement(ChunkDiagnosticsPlugin::INDEX_MEMORY, || mebibytes(indices));
+    diagnostics.add_measurement(ChunkDiagnosticsPlugin::QUADS, || quads as f64);
 }
@@ -1,39 +1,229 @@
+use std::ops::RangeInclusive;
+
+use bevy::utils::HashMap;
+use itertools::Itertools;
 use noise::{Fbm, NoiseFn, Perlin};
+use parking_lot::RwLock;
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
-use splines::{Interpolation, Key, Spline};
 
 use crate::{
+    biome::{Biome, BiomeRegistry, Climate},
     block::Block,
+    block_definitions::BlockModels,
+    block_model::BlockModel,
+    feature::{Feature, FEATURE_REACH},
+    light::transmits,
+    sky_map::SkyMap,
     voxel::{
-        block_pos::BlockPos, chunk::iter_blocks, chunk_data::ChunkData, chunk_index::ChunkIndex,
+        block_pos::BlockPos, chunk::CHUNK_SIZE, chunk_data::ChunkData, chunk_index::ChunkIndex,
         chunk_pos::ChunkPos,
     },
+    world_generator::WorldGenerator,
 };
 
+/// How far caves fade out towards the ends of their height range and towards the surface, in
+/// blocks.
+const CAVE_FADE: f64 = 8.0;
+
+/// How many times each column of chunks tries to place a feature.
+const FEATURE_ATTEMPTS: usize = 16;
+
+/// The sea only fills the ground below sea level where the continentalness is lower than this, so
+/// valleys inland stay dry.
+const OCEAN_CONTINENTALNESS: f64 = -0.1;
+
+/// The level is split into square cells this many blocks wide, each with at most one lake. It's a
+/// multiple of the chunk size, so every chunk is inside a single cell.
+const LAKE_CELL_SIZE: i64 = 128;
+const LAKE_CHANCE: f64 = 0.35;
+
+/// How far around a lake the ground has to be higher than its water. Wide enough to reach every
+/// column touching the lake, diagonals included.
+const SHORE_WIDTH: f64 = 1.5;
+
+/// Sea and lake floors deeper than this under the water are gravel rather than sand.
+const SAND_DEPTH: f64 = 4.0;
+
+/// How far from the sea, in blocks, the ground around sea level is sand.
+const BEACH_WIDTH: i64 = 3;
+/// How high above the sea beaches reach.
+const BEACH_HEIGHT: f64 = 2.0;
+
+/// Where and how much of the underground gets hollowed out.
+#[derive(Debug, Clone)]
+pub struct CaveSettings {
+    /// From 0 for no caves to 1 for an underground that's mostly tunnels and caverns.
+    pub density: f64,
+    /// The lowest height caves are carved at.
+    pub min_height: f64,
+    /// The highest height caves are carved at.
+    pub max_height: f64,
+    /// How many blocks of ground caves leave above them, so they don't punch holes in the surface.
+    /// Zero lets them open up at the surface.
+    pub surface_depth: f64,
+}
+
+impl Default for CaveSettings {
+    fn default() -> Self {
+        Self {
+            density: 0.5,
+            min_height: -64.0,
+            max_height: 150.0,
+            surface_depth: 4.0,
+        }
+    }
+}
+
+/// Replaces patches of one block underground with another. Small patches make ore veins, and large
+/// frequent ones make strata of different stone.
+#[derive(Debug, Clone)]
+pub struct OreRule {
+    pub block: Block,
+    /// The block the patches grow into. Rules run in order, so a later rule can grow into the
+    /// block an earlier one placed.
+    pub host: Block,
+    /// The lowest height patches are placed at.
+    pub min_height: f64,
+    /// The highest height patches are placed at.
+    pub max_height: f64,
+    /// Roughly how wide a patch is, in blocks. Patches are half as tall as they're wide.
+    pub size: f64,
+    /// From 0 to 1, roughly how much of the host block in the height range gets replaced.
+    pub frequency: f64,
+}
+
+impl OreRule {
+    fn default_rules() -> Vec<Self> {
+        let rule = |block, host, min_height, max_height, size, frequency| Self {
+            block,
+            host,
+            min_height,
+            max_height,
+            size,
+            frequency,
+        };
+
+        vec![
+            rule(Block::Slate, Block::Rock, -256.0, 8.0, 48.0, 0.6),
+            rule(Block::CoalOre, Block::Rock, 0.0, 200.0, 3.0, 0.08),
+            rule(Block::IronOre, Block::Rock, -48.0, 80.0, 2.5, 0.06),
+            rule(Block::IronOre, Block::Slate, -48.0, 80.0, 2.5, 0.06),
+            rule(Block::GoldOre, Block::Slate, -256.0, 0.0, 2.0, 0.04),
+        ]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GeneratorSettings {
+    /// The sea fills everything below this height in low-lying coastal regions.
+    pub sea_level: f64,
+    pub caves: CaveSettings,
+    pub ores: Vec<OreRule>,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        Self {
+            sea_level: 64.0,
+            caves: CaveSettings::default(),
+            ores: OreRule::default_rules(),
+        }
+    }
+}
+
+/// A round hollow in the ground filled with water.
+#[derive(Debug, Clone, Copy)]
+struct Lake {
+    x: f64,
+    z: f64,
+    radius: f64,
+    /// How far below the water the middle of the lake bed is.
+    depth: f64,
+    /// The height of the top of the water.
+    level: f64,
+}
+
 pub struct LevelGenerator {
     temperature: Perlin,
+    humidity: Perlin,
     continentalness: Fbm<Perlin>,
-    spline: Spline<f64, f64>,
+    erosion: Fbm<Perlin>,
+    biomes: BiomeRegistry,
+    /// Mixed into the position hash that picks where decorations grow.
+    decoration_seed: u32,
+    /// Mixed into the position of a chunk column to seed the features placed from it.
+    feature_seed: u64,
+    sea_level: f64,
+    /// Mixed into the position of a lake cell to seed the lake in it.
+    lake_seed: u64,
+    /// The lake of every cell that was looked at, keyed by the cell's corner. Finding one means
+    /// searching all the ground around it, and every chunk near a cell needs it several times.
+    lakes: RwLock<HashMap<BlockPos, Option<Lake>>>,
+    caves: CaveSettings,
+    /// Worm caves follow the lines where both of these are close to zero.
+    worm_a: Perlin,
+    worm_b: Perlin,
+    /// Cheese caves are the large blobs where this is high.
+    cheese: Fbm<Perlin>,
+    /// Varies how deep the soil goes before rock.
+    soil: Perlin,
+    /// Each rule places its patches where its noise is high.
+    ores: Vec<(OreRule, Perlin)>,
 }
 
 impl LevelGenerator {
     pub fn new(seed: u64) -> Self {
-        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        Self::with_settings(seed, GeneratorSettings::default())
+    }
 
-        let spline = Spline::from_vec(vec![
-            Key::new(-1.0, 50.0, Interpolation::Linear),
-            Key::new(0.3, 100.0, Interpolation::Linear),
-            Key::new(1.0, 150.0, Interpolation::Linear),
-        ]);
+    pub fn with_settings(seed: u64, settings: GeneratorSettings) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
 
         let mut continentalness = Fbm::new(rng.gen());
         continentalness.octaves = 4;
 
+        let temperature = Perlin::new(rng.gen());
+
+        let mut cheese = Fbm::new(rng.gen());
+        cheese.octaves = 3;
+
+        let worm_a = Perlin::new(rng.gen());
+        let worm_b = Perlin::new(rng.gen());
+
+        let mut erosion = Fbm::new(rng.gen());
+        erosion.octaves = 3;
+
+        let humidity = Perlin::new(rng.gen());
+        let decoration_seed = rng.gen();
+        let soil = Perlin::new(rng.gen());
+
+        let feature_seed = rng.gen();
+        let lake_seed = rng.gen();
+
+        let ores = settings
+            .ores
+            .into_iter()
+            .map(|rule| (rule, Perlin::new(rng.gen())))
+            .collect();
+
         Self {
-            temperature: Perlin::new(rng.gen()),
+            temperature,
+            humidity,
             continentalness,
-            spline,
+            erosion,
+            biomes: BiomeRegistry::default(),
+            decoration_seed,
+            feature_seed,
+            sea_level: settings.sea_level,
+            lake_seed,
+            lakes: RwLock::default(),
+            caves: settings.caves,
+            worm_a,
+            worm_b,
+            cheese,
+            soil,
+            ores,
         }
     }
 }
@@ -44,40 +234,444 @@ impl Default for LevelGenerator {
     }
 }
 
+impl WorldGenerator for LevelGenerator {
+    fn generate_chunk(&self, pos: ChunkPos, models: &BlockModels) -> ChunkData {
+        LevelGenerator::generate_chunk(self, pos, models)
+    }
+
+    fn generate_sky_map(&self, pos: ChunkPos, models: &BlockModels) -> SkyMap {
+        LevelGenerator::generate_sky_map(self, pos, models)
+    }
+
+    fn biome(&self, x: i64, z: i64) -> Option<&str> {
+        Some(self.column(x, z).biome.name)
+    }
+}
+
+/// What every block in a column of the level shares.
+pub struct Column<'a> {
+    pub climate: Climate,
+    pub biome: &'a Biome,
+    /// The height of the terrain, blended between nearby biomes.
+    pub height: f64,
+    /// How far below the terrain height rock begins.
+    pub soil_depth: f64,
+    /// The height water fills the column up to, if it's in the sea or a lake.
+    pub water_level: Option<f64>,
+    /// Whether the column is dry ground at the edge of the sea, which is sand all the way down
+    /// through its soil.
+    pub beach: bool,
+}
+
+impl Column<'_> {
+    fn is_underwater(&self, y: f64) -> bool {
+        self.water_level.is_some_and(|level| y <= level)
+    }
+}
+
 impl LevelGenerator {
-    pub fn generate_chunk(&self, pos: ChunkPos) -> ChunkData {
+    pub fn generate_chunk(&self, pos: ChunkPos, models: &BlockModels) -> ChunkData {
         let mut chunk = ChunkData::default();
-        for (x, y, z) in iter_blocks() {
-            let block_pos = pos.block_pos() + BlockPos::new(x as i64, y as i64, z as i64);
-            let block = self.generate_block(block_pos);
-            *chunk.block_mut(ChunkIndex::new(x, y, z)) = block;
+        let origin = pos.block_pos();
+        let lake = self.lake(origin.x, origin.z);
+        for (x, z) in (0..CHUNK_SIZE).cartesian_product(0..CHUNK_SIZE) {
+            let column = self.column_in_lake(origin.x + x as i64, origin.z + z as i64, lake);
+            for y in 0..CHUNK_SIZE {
+                let block_pos = origin + BlockPos::new(x as i64, y as i64, z as i64);
+                let block = self.generate_block(block_pos, &column);
+                *chunk.block_mut(ChunkIndex::new(x, y, z)) = block;
+            }
         }
+        self.place_features(pos, &mut chunk, models);
         chunk
     }
 
-    fn generate_block(&self, pos: BlockPos) -> Option<Block> {
-        let temperature = self
-            .temperature
-            .get([pos.x as f64 / 700.0, pos.z as f64 / 700.0]);
+    /// Adds the parts of features that reach into the chunk.
+    ///
+    /// Features are seeded per column of chunks rather than generated along with a chunk, so any
+    /// chunk can work out which of its neighbors' features overlap it without generating them.
+    /// That keeps the result the same whatever order chunks load in.
+    fn place_features(&self, pos: ChunkPos, chunk: &mut ChunkData, models: &BlockModels) {
+        let size = CHUNK_SIZE as i64;
+        let min_y = pos.block_pos().y - FEATURE_REACH;
+        let max_y = pos.block_pos().y + size - 1 + FEATURE_REACH;
+
+        self.column_features(pos, min_y..=max_y, |block_pos, block| {
+            if block_pos.chunk_pos() != pos {
+                return;
+            }
+
+            let (x, y, z) = block_pos.relative_pos();
+            let existing = chunk.block_mut(ChunkIndex::new(x, y, z));
+            if can_hold_feature(*existing, models) {
+                *existing = Some(block);
+            }
+        });
+    }
+
+    /// Calls `place` with every block of the features standing at the given heights that falls
+    /// in the chunk's column, in the order they're placed.
+    fn column_features(
+        &self,
+        pos: ChunkPos,
+        heights: RangeInclusive<i64>,
+        mut place: impl FnMut(BlockPos, Block),
+    ) {
+        let size = CHUNK_SIZE as i64;
 
-        let continentalness = self
-            .continentalness
-            .get([pos.x as f64 / 400.0, pos.z as f64 / 400.0]);
+        // Always in the same order, so overlapping features resolve the same way every time.
+        for (dx, dz) in (-1..=1).cartesian_product(-1..=1) {
+            let origin = ChunkPos::new(pos.x + dx, 0, pos.z + dz).block_pos();
+            let hash = origin.hash_u32() as u64;
+            let mut rng = ChaCha8Rng::seed_from_u64(self.feature_seed ^ hash);
+            let lake = self.lake(origin.x, origin.z);
 
-        let terrain_height = self.spline.clamped_sample(continentalness).unwrap();
+            for _ in 0..FEATURE_ATTEMPTS {
+                // Everything is drawn up front, so skipped attempts don't shift the ones after.
+                let x = origin.x + rng.gen_range(0..size);
+                let z = origin.z + rng.gen_range(0..size);
+                let roll: f64 = rng.gen();
+                let feature_seed: u64 = rng.gen();
 
-        if temperature > 0.4 {
-            if pos.y as f64 <= terrain_height {
-                Some(Block::Sand)
+                let column = self.column_in_lake(x, z, lake);
+                let Some(feature) = Self::pick_feature(column.biome, roll) else {
+                    continue;
+                };
+
+                let anchor = BlockPos::new(x, column.height.floor() as i64 + 1, z);
+                if !heights.contains(&anchor.y) {
+                    continue;
+                }
+
+                // Features stand on dry ground, not underwater, on a beach or over the mouth of a
+                // cave.
+                if column.is_underwater(anchor.y as f64)
+                    || column.beach
+                    || self.is_cave(anchor + BlockPos::NEG_Y, column.height)
+                {
+                    continue;
+                }
+
+                let mut rng = ChaCha8Rng::seed_from_u64(feature_seed);
+                feature.blocks(&mut rng, |offset, block| {
+                    let block_pos = anchor + offset;
+                    if block_pos.chunk_pos().column() == pos.column() {
+                        place(block_pos, block);
+                    }
+                });
+            }
+        }
+    }
+
+    /// The highest block in every column of the chunk's column that stops sunlight, as it's
+    /// generated. Trees and boulders are placed the same way as in the chunks themselves, so
+    /// their shade lines up.
+    pub fn generate_sky_map(&self, pos: ChunkPos, models: &BlockModels) -> SkyMap {
+        let origin = pos.column().block_pos();
+        let lake = self.lake(origin.x, origin.z);
+        let columns = (0..CHUNK_SIZE)
+            .cartesian_product(0..CHUNK_SIZE)
+            .map(|(x, z)| self.column_in_lake(origin.x + x as i64, origin.z + z as i64, lake))
+            .collect_vec();
+        let index = |pos: BlockPos| {
+            let (x, _, z) = pos.relative_pos();
+            x * CHUNK_SIZE + z
+        };
+
+        // Scanned down from just above the terrain, through any cave opening up at the surface.
+        let bottom = self.caves.min_height.floor() as i64;
+        let mut tops = (0..CHUNK_SIZE as i64)
+            .cartesian_product(0..CHUNK_SIZE as i64)
+            .map(|(x, z)| {
+                let mut block_pos = origin + BlockPos::new(x, 0, z);
+                let column = &columns[index(block_pos)];
+                block_pos.y = column.height.floor() as i64 + 1;
+                while block_pos.y > bottom
+                    && transmits(self.generate_block(block_pos, column), models)
+                {
+                    block_pos.y -= 1;
+                }
+                block_pos.y
+            })
+            .collect_vec();
+
+        // Features overlapping each other keep whichever was placed first.
+        let mut blocks = HashMap::new();
+        self.column_features(pos, i64::MIN..=i64::MAX, |block_pos, block| {
+            let existing = blocks
+                .entry(block_pos)
+                .or_insert_with(|| self.generate_block(block_pos, &columns[index(block_pos)]));
+            if can_hold_feature(*existing, models) {
+                *existing = Some(block);
+            }
+        });
+
+        for (block_pos, block) in blocks {
+            if !transmits(block, models) {
+                let top = &mut tops[index(block_pos)];
+                *top = (*top).max(block_pos.y);
+            }
+        }
+
+        SkyMap::new(|x, z| tops[x * CHUNK_SIZE + z])
+    }
+
+    fn pick_feature(biome: &Biome, mut roll: f64) -> Option<Feature> {
+        for rule in &biome.features {
+            if roll < rule.chance {
+                return Some(rule.feature);
+            }
+            roll -= rule.chance;
+        }
+        None
+    }
+
+    pub fn climate(&self, x: i64, z: i64) -> Climate {
+        let (x, z) = (x as f64, z as f64);
+        Climate {
+            temperature: self.temperature.get([x / 700.0, z / 700.0]),
+            humidity: self.humidity.get([x / 600.0, z / 600.0]),
+            continentalness: self.continentalness.get([x / 400.0, z / 400.0]),
+            erosion: self.erosion.get([x / 500.0, z / 500.0]),
+        }
+    }
+
+    pub fn column(&self, x: i64, z: i64) -> Column<'_> {
+        self.column_in_lake(x, z, self.lake(x, z))
+    }
+
+    /// The column without any lake carved into it.
+    fn dry_column(&self, x: i64, z: i64) -> Column<'_> {
+        let climate = self.climate(x, z);
+        let height = self.biomes.height(climate);
+        let in_sea = self.is_sea(climate, height);
+        let beach = !in_sea
+            && (self.sea_level - SAND_DEPTH..self.sea_level + BEACH_HEIGHT).contains(&height)
+            && self.near_sea(x, z, climate);
+
+        Column {
+            climate,
+            biome: self.biomes.biome(climate),
+            height,
+            soil_depth: 4.0 + 2.5 * self.soil.get([x as f64 / 40.0, z as f64 / 40.0]),
+            water_level: in_sea.then_some(self.sea_level),
+            beach,
+        }
+    }
+
+    fn is_sea(&self, climate: Climate, height: f64) -> bool {
+        climate.continentalness < OCEAN_CONTINENTALNESS && height < self.sea_level
+    }
+
+    /// Whether there's sea within a beach's width of the column, looking outwards in the eight
+    /// directions around it.
+    fn near_sea(&self, x: i64, z: i64, climate: Climate) -> bool {
+        // Continentalness changes slowly, so columns well inland can't be this close to the sea.
+        if climate.continentalness > OCEAN_CONTINENTALNESS + 0.05 {
+            return false;
+        }
+
+        (-1..=1)
+            .cartesian_product(-1..=1)
+            .filter(|&direction| direction != (0, 0))
+            .cartesian_product(1..=BEACH_WIDTH)
+            .any(|((dx, dz), distance)| {
+                let climate = self.climate(x + dx * distance, z + dz * distance);
+                self.is_sea(climate, self.biomes.height(climate))
+            })
+    }
+
+    /// The column, carved out by the lake of its cell if it's inside it.
+    fn column_in_lake(&self, x: i64, z: i64, lake: Option<Lake>) -> Column<'_> {
+        let mut column = self.dry_column(x, z);
+
+        if let Some(lake) = lake {
+            let distance = (x as f64 - lake.x).hypot(z as f64 - lake.z) / lake.radius;
+            if distance < 1.0 {
+                let bed = lake.level - lake.depth * (1.0 - distance * distance);
+                column.height = column.height.min(bed);
+                column.water_level = Some(lake.level);
+                column.beach = false;
+            }
+        }
+
+        column
+    }
+
+    /// The lake in the cell containing the position, if it has one.
+    fn lake(&self, x: i64, z: i64) -> Option<Lake> {
+        let cell = BlockPos::new(
+            x.div_euclid(LAKE_CELL_SIZE) * LAKE_CELL_SIZE,
+            0,
+            z.div_euclid(LAKE_CELL_SIZE) * LAKE_CELL_SIZE,
+        );
+
+        if let Some(&lake) = self.lakes.read().get(&cell) {
+            return lake;
+        }
+
+        let lake = self.generate_lake(cell);
+        self.lakes.write().insert(cell, lake);
+        lake
+    }
+
+    /// Places the lake of the cell with the given corner, if it gets one.
+    ///
+    /// Lakes only form in hollows inland, with their water a block below the lowest point of the
+    /// ground around them so it doesn't spill over.
+    fn generate_lake(&self, cell: BlockPos) -> Option<Lake> {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.lake_seed ^ cell.hash_u32() as u64);
+
+        let roll: f64 = rng.gen();
+        let radius = rng.gen_range(10.0..24.0);
+        let depth = rng.gen_range(3.0..7.0);
+        // Far enough from the edges of the cell that the lake fits inside it.
+        let margin = radius + 2.0;
+        let x = cell.x as f64 + rng.gen_range(margin..LAKE_CELL_SIZE as f64 - margin);
+        let z = cell.z as f64 + rng.gen_range(margin..LAKE_CELL_SIZE as f64 - margin);
+
+        if roll >= LAKE_CHANCE {
+            return None;
+        }
+
+        let center = self.dry_column(x as i64, z as i64);
+        if center.water_level.is_some() {
+            return None;
+        }
+
+        // Every column bordering the lake, so no dip in the ground around it is missed and the
+        // water never stands above its neighbors.
+        let reach = (radius + SHORE_WIDTH).ceil() as i64;
+        let (center_x, center_z) = (x.floor() as i64, z.floor() as i64);
+        let shore = (-reach..=reach)
+            .cartesian_product(-reach..=reach)
+            .map(|(dx, dz)| (center_x + dx, center_z + dz))
+            .filter(|&(shore_x, shore_z)| {
+                let distance = (shore_x as f64 - x).hypot(shore_z as f64 - z);
+                (radius..radius + SHORE_WIDTH).contains(&distance)
+            })
+            .map(|(shore_x, shore_z)| self.dry_column(shore_x, shore_z).height)
+            .fold(f64::INFINITY, f64::min);
+
+        let level = shore.floor() - 1.0;
+
+        // On a slope the lake would be dug into the hillside, so it has to sit in a hollow.
+        if center.height > level + 3.0 || level <= self.sea_level {
+            return None;
+        }
+
+        Some(Lake {
+            x,
+            z,
+            radius,
+            depth,
+            level,
+        })
+    }
+
+    fn generate_block(&self, pos: BlockPos, column: &Column) -> Option<Block> {
+        let y = pos.y as f64;
+        let terrain_height = column.height;
+
+        if column.is_underwater(y) && y > terrain_height {
+            return Some(Block::Water);
+        }
+
+        if y > terrain_height {
+            // Decorations grow on the surface, unless a cave opened up underneath them. Nothing
+            // grows in the sand of beaches.
+            let surface = pos + BlockPos::NEG_Y;
+            if surface.y as f64 > terrain_height
+                || self.is_cave(surface, terrain_height)
+                || column.beach
+            {
+                return None;
+            }
+            return self.decoration(pos, column.biome);
+        }
+
+        if self.is_cave(pos, terrain_height) {
+            None
+        } else if y <= terrain_height - column.soil_depth {
+            Some(self.ore(pos, Block::Rock))
+        } else if column.beach {
+            Some(Block::Sand)
+        } else if y <= terrain_height - 1.0 {
+            Some(column.biome.subsurface)
+        } else if let Some(water_level) = column.water_level.filter(|&level| y < level) {
+            // Shallow water has a sandy floor, and deeper water is gravel.
+            if water_level - y > SAND_DEPTH {
+                Some(Block::Gravel)
             } else {
-                None
+                Some(Block::Sand)
             }
-        } else if pos.y as f64 <= terrain_height - 1.0 {
-            Some(Block::Dirt)
-        } else if pos.y as f64 <= terrain_height {
-            Some(Block::Grass)
         } else {
-            None
+            Some(column.biome.surface)
+        }
+    }
+
+    /// Runs the ore rules over a block of stone.
+    fn ore(&self, pos: BlockPos, mut block: Block) -> Block {
+        let y = pos.y as f64;
+        for (rule, noise) in &self.ores {
+            if block != rule.host || y < rule.min_height || y > rule.max_height {
+                continue;
+            }
+
+            // Perlin noise has features about half a unit across.
+            let scale = 0.5 / rule.size.max(0.5);
+            let value = noise.get([pos.x as f64 * scale, y * scale * 2.0, pos.z as f64 * scale]);
+            if value > 0.6 - 0.8 * rule.frequency.clamp(0.0, 1.0) {
+                block = rule.block;
+            }
+        }
+        block
+    }
+
+    fn decoration(&self, pos: BlockPos, biome: &Biome) -> Option<Block> {
+        let mut roll = (pos.hash_u32() ^ self.decoration_seed) as f64 / u32::MAX as f64;
+        for decoration in &biome.decorations {
+            if roll < decoration.chance {
+                return Some(decoration.block);
+            }
+            roll -= decoration.chance;
         }
+        None
+    }
+}
+
+/// Features only grow into air and plants, never into the ground or each other.
+fn can_hold_feature(block: Option<Block>, models: &BlockModels) -> bool {
+    block.map_or(true, |block| block.model(models) == &BlockModel::Cross)
+}
+
+impl LevelGenerator {
+    /// Whether the block is carved out by a cave, given the height of the terrain above it.
+    fn is_cave(&self, pos: BlockPos, terrain_height: f64) -> bool {
+        let caves = &self.caves;
+        let y = pos.y as f64;
+
+        // Caves narrow and close off towards the ends of their range, instead of being cut flat.
+        let fade = |distance: f64| (distance / CAVE_FADE).clamp(0.0, 1.0);
+        let mut weight = fade(y - caves.min_height).min(fade(caves.max_height - y));
+        if caves.surface_depth > 0.0 {
+            weight = weight.min(fade(terrain_height - caves.surface_depth - y));
+        }
+
+        let density = caves.density.clamp(0.0, 1.0) * weight;
+        if density <= 0.0 {
+            return false;
+        }
+
+        // Squashed vertically, so tunnels mostly run sideways.
+        let worm_pos = [pos.x as f64 / 80.0, y / 40.0, pos.z as f64 / 80.0];
+        let (a, b) = (self.worm_a.get(worm_pos), self.worm_b.get(worm_pos));
+        let radius = 0.1 * density;
+        if a * a + b * b < radius * radius {
+            return true;
+        }
+
+        let cheese_pos = [pos.x as f64 / 120.0, y / 60.0, pos.z as f64 / 120.0];
+        self.cheese.get(cheese_pos) > 0.9 - 0.6 * density
     }
 }
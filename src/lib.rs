@@ -0,0 +1,37 @@
+#![allow(dead_code)]
+#![allow(clippy::type_complexity)]
+#![allow(clippy::too_many_arguments)]
+
+use bevy::prelude::*;
+
+pub mod ambient_occlusion;
+pub mod biome;
+pub mod block;
+pub mod block_definitions;
+pub mod block_model;
+pub mod chunk;
+pub mod chunk_collider;
+pub mod chunk_diagnostics;
+pub mod chunk_material;
+pub mod chunk_visibility;
+pub mod day_cycle;
+pub mod egui_menu;
+pub mod export;
+pub mod feature;
+pub mod greedy_mesher;
+pub mod level;
+pub mod level_generator;
+pub mod light;
+pub mod mesh_builder;
+pub mod player;
+pub mod plugins;
+pub mod sky_map;
+pub mod voxel;
+pub mod world_generator;
+
+#[derive(States, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameState {
+    #[default]
+    LoadingAssets,
+    InGame,
+}
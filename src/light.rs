@@ -0,0 +1,565 @@
+use std::sync::Arc;
+
+use bevy::utils::HashMap;
+use itertools::Itertools;
+use parking_lot::RwLock;
+
+use crate::{
+    block::{Block, BlockFace},
+    block_definitions::BlockModels,
+    sky_map::ChunkSky,
+    voxel::{
+        block_pos::BlockPos,
+        chunk::{iter_blocks, Chunk, CHUNK_SIZE},
+        chunk_index::ChunkIndex,
+        chunk_pos::ChunkPos,
+        chunk_sections::ChunkSections,
+    },
+};
+
+/// The brightest a light level can be, which is also how far light spreads from its source.
+pub const MAX_LIGHT: u8 = 15;
+
+/// Light levels of sunlight and of the red, green and blue light given off by blocks, four bits
+/// each.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Light(u16);
+
+impl Light {
+    /// Full sunlight, for places the light engine doesn't cover.
+    pub const SKY: Self = Self(MAX_LIGHT as u16);
+
+    pub fn sun(self) -> u8 {
+        self.get(Channel::Sun)
+    }
+
+    /// The levels of red, green and blue block light.
+    pub fn color(self) -> [u8; 3] {
+        [Channel::Red, Channel::Green, Channel::Blue].map(|channel| self.get(channel))
+    }
+
+    /// Sunlight in the lowest four bits, followed by red, green and blue.
+    pub fn to_bits(self) -> u32 {
+        self.0 as u32
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits as u16)
+    }
+
+    /// The brighter level of each channel of both lights.
+    fn brightest(self, other: Self) -> Self {
+        let mut light = self;
+        for channel in Channel::ALL {
+            light.set(channel, self.get(channel).max(other.get(channel)));
+        }
+        light
+    }
+
+    fn get(self, channel: Channel) -> u8 {
+        (self.0 >> channel.shift() & 0xf) as u8
+    }
+
+    fn set(&mut self, channel: Channel, value: u8) {
+        self.0 = self.0 & !(0xf << channel.shift()) | (value as u16) << channel.shift();
+    }
+}
+
+/// Light spreads through each channel on its own, so colored lights mix where they overlap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Channel {
+    /// Comes down from the sky, and only fades when it's spreading sideways or up. Blocks open
+    /// to the sky give it off as if they were lights.
+    Sun,
+    Red,
+    Green,
+    Blue,
+}
+
+impl Channel {
+    const ALL: [Self; 4] = [Self::Sun, Self::Red, Self::Green, Self::Blue];
+
+    fn shift(self) -> u16 {
+        match self {
+            Self::Sun => 0,
+            Self::Red => 4,
+            Self::Green => 8,
+            Self::Blue => 12,
+        }
+    }
+}
+
+/// The light level of every block in a chunk. Kept alongside the chunk's blocks rather than
+/// saved with them, since it's worked out again whenever the chunk is loaded.
+#[derive(Clone)]
+pub struct LightData(Vec<Light>);
+
+pub type ChunkLight = Arc<RwLock<LightData>>;
+
+impl Default for LightData {
+    fn default() -> Self {
+        Self(vec![Light::default(); CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE])
+    }
+}
+
+impl LightData {
+    /// Lights a chunk on its own, from the sky above its column and with darkness around it.
+    /// The chunks around it are taken into account once it's added to the level.
+    pub fn new(pos: ChunkPos, chunk: &Chunk, sky: &ChunkSky, models: &BlockModels) -> Self {
+        let light = ChunkLight::default();
+        let chunks = HashMap::from([(pos, chunk.clone())]);
+        let lights = HashMap::from([(pos, light.clone())]);
+        let skies = HashMap::from([(pos.column(), sky.clone())]);
+
+        LightEngine::new(&chunks, &lights, &skies, models).light_chunk(pos);
+
+        let light = light.read().clone();
+        light
+    }
+
+    pub fn get(&self, index: ChunkIndex) -> Light {
+        self.0[index.as_usize()]
+    }
+
+    fn get_mut(&mut self, index: ChunkIndex) -> &mut Light {
+        &mut self.0[index.as_usize()]
+    }
+}
+
+/// Whether light can pass through the block.
+pub fn transmits(block: Option<Block>, models: &BlockModels) -> bool {
+    !block.is_some_and(|block| block.casts_occlusion(models))
+}
+
+/// Spreads light between the loaded chunks of a level, keeping track of the sections whose light
+/// changed so they can be remeshed.
+///
+/// Light is added with a flood fill from its sources, and removed by first flood filling
+/// darkness over everything that was lit by the removed light, then filling light back in from
+/// whatever is still bright at the edges of the darkened area.
+///
+/// Sunlight comes from the sky map of each column rather than from the chunks above, so loading
+/// or unloading those doesn't change what's open to the sky below them.
+pub struct LightEngine<'a> {
+    chunks: &'a HashMap<ChunkPos, Chunk>,
+    lights: &'a HashMap<ChunkPos, ChunkLight>,
+    skies: &'a HashMap<ChunkPos, ChunkSky>,
+    models: &'a BlockModels,
+    changed: HashMap<ChunkPos, ChunkSections>,
+}
+
+impl<'a> LightEngine<'a> {
+    pub fn new(
+        chunks: &'a HashMap<ChunkPos, Chunk>,
+        lights: &'a HashMap<ChunkPos, ChunkLight>,
+        skies: &'a HashMap<ChunkPos, ChunkSky>,
+        models: &'a BlockModels,
+    ) -> Self {
+        Self {
+            chunks,
+            lights,
+            skies,
+            models,
+            changed: HashMap::default(),
+        }
+    }
+
+    /// The sections whose light changed, including the ones next to changed blocks since their
+    /// faces are lit by them.
+    pub fn changed(self) -> HashMap<ChunkPos, ChunkSections> {
+        self.changed
+    }
+
+    /// Lights a chunk from the sky and its own light sources, ignoring its neighbors.
+    fn light_chunk(&mut self, pos: ChunkPos) {
+        let origin = pos.block_pos();
+
+        for channel in Channel::ALL {
+            let mut sources = Vec::new();
+
+            for (x, y, z) in iter_blocks() {
+                let block_pos = origin + BlockPos::new(x as i64, y as i64, z as i64);
+
+                let level = self.emission(block_pos, channel);
+                if level > 0 {
+                    self.set(block_pos, channel, level);
+                    sources.push(block_pos);
+                }
+            }
+
+            self.propagate(channel, sources);
+        }
+    }
+
+    /// Connects the light of a chunk that was just added to the level with its neighbors.
+    pub fn insert_chunk(&mut self, pos: ChunkPos) {
+        // Columns whose sky map stops at the top of the chunk, since it wasn't loaded before, can
+        // now be looked through
+        let origin = pos.block_pos();
+        for (x, z) in (0..CHUNK_SIZE as i64).cartesian_product(0..CHUNK_SIZE as i64) {
+            let top = origin + BlockPos::new(x, CHUNK_SIZE as i64 - 1, z);
+            if self.sky_top(top) == Some(top.y) {
+                let sky_top = self.find_sky_top(top);
+                self.set_sky_top(top, sky_top);
+            }
+        }
+
+        // Light flows across every side, in both directions
+        let seeds: Vec<BlockPos> = border_blocks()
+            .flat_map(|(x, y, z)| {
+                let block_pos = origin + BlockPos::new(x as i64, y as i64, z as i64);
+                let outside = neighbors(block_pos)
+                    .map(|(_, neighbor)| neighbor)
+                    .filter(move |neighbor| neighbor.chunk_pos() != pos);
+                std::iter::once(block_pos).chain(outside)
+            })
+            .collect();
+
+        for channel in Channel::ALL {
+            self.propagate(channel, seeds.clone());
+        }
+    }
+
+    /// Takes the light that a chunk spread into its neighbors back out of them, once the chunk and
+    /// its light were dropped from the level. Whatever is open to the sky below it stays lit, as
+    /// it lights itself.
+    pub fn remove_chunk(&mut self, pos: ChunkPos, light: &LightData) {
+        let origin = pos.block_pos();
+
+        // The blocks of the chunk itself are gone, so only their neighbors outside it darken
+        for channel in Channel::ALL {
+            let removed = border_blocks()
+                .filter_map(|(x, y, z)| {
+                    let old = light.get(ChunkIndex::new(x, y, z)).get(channel);
+                    let block_pos = origin + BlockPos::new(x as i64, y as i64, z as i64);
+                    (old > 0).then_some((block_pos, old))
+                })
+                .collect();
+            self.remove(channel, removed);
+        }
+    }
+
+    /// Updates the light around a block that was just placed or removed, along with the sky map
+    /// of its column. Returns whether the sky map changed.
+    pub fn update_block(&mut self, pos: BlockPos) -> bool {
+        let sky_changed = self.update_sky(pos);
+
+        for channel in Channel::ALL {
+            let Some(old) = self.get(pos, channel) else {
+                break;
+            };
+
+            let emission = self.emission(pos, channel);
+            self.set(pos, channel, emission);
+            self.remove(channel, vec![(pos, old)]);
+
+            // Light from around the block can now flow into it, or around it
+            let mut seeds: Vec<BlockPos> = neighbors(pos).map(|(_, pos)| pos).collect();
+            seeds.push(pos);
+
+            self.propagate(channel, seeds);
+        }
+
+        sky_changed
+    }
+
+    /// Raises the top of the block's column to it if it now stops sunlight, or looks further
+    /// down if it was the top and no longer does.
+    fn update_sky(&mut self, pos: BlockPos) -> bool {
+        let Some(top) = self.sky_top(pos) else {
+            return false;
+        };
+
+        let new_top = if !self.transmits(pos) {
+            top.max(pos.y)
+        } else if pos.y == top {
+            self.find_sky_top(pos)
+        } else {
+            top
+        };
+
+        self.set_sky_top(pos, new_top);
+        new_top != top
+    }
+
+    /// The first block from the given one down that stops sunlight, or the top of the first
+    /// chunk that isn't loaded if that comes first.
+    fn find_sky_top(&self, mut pos: BlockPos) -> i64 {
+        while self.chunks.contains_key(&pos.chunk_pos()) && self.transmits(pos) {
+            pos = pos + BlockPos::NEG_Y;
+        }
+        pos.y
+    }
+
+    fn sky_top(&self, pos: BlockPos) -> Option<i64> {
+        let sky = self.skies.get(&pos.chunk_pos().column())?;
+        let top = sky.read().top(pos);
+        Some(top)
+    }
+
+    fn set_sky_top(&mut self, pos: BlockPos, top: i64) {
+        if let Some(sky) = self.skies.get(&pos.chunk_pos().column()) {
+            sky.write().set_top(pos, top);
+        }
+    }
+
+    /// Whether nothing above the block keeps sunlight from it. The top of the column is only
+    /// open if it doesn't stop light itself, which is the case where it's the top of a chunk
+    /// that wasn't loaded.
+    fn open_to_sky(&self, pos: BlockPos) -> bool {
+        self.sky_top(pos).is_some_and(|top| pos.y >= top) && self.transmits(pos)
+    }
+
+    /// Spreads light outwards from the given blocks, for as long as it gets brighter.
+    fn propagate(&mut self, channel: Channel, mut queue: Vec<BlockPos>) {
+        while let Some(pos) = queue.pop() {
+            let Some(value) = self.get(pos, channel) else {
+                continue;
+            };
+
+            if value <= 1 {
+                continue;
+            }
+
+            for (face, neighbor) in neighbors(pos) {
+                let next =
+                    if channel == Channel::Sun && face == BlockFace::Bottom && value == MAX_LIGHT {
+                        MAX_LIGHT
+                    } else {
+                        value - 1
+                    };
+
+                if self
+                    .get(neighbor, channel)
+                    .is_some_and(|light| light < next)
+                    && self.transmits(neighbor)
+                {
+                    self.set(neighbor, channel, next);
+                    queue.push(neighbor);
+                }
+            }
+        }
+    }
+
+    /// Darkens everything that was lit by the given blocks at their old light levels, then fills
+    /// light back in from around the darkened area.
+    fn remove(&mut self, channel: Channel, mut queue: Vec<(BlockPos, u8)>) {
+        let mut relight = Vec::new();
+
+        while let Some((pos, old)) = queue.pop() {
+            for (face, neighbor) in neighbors(pos) {
+                let Some(light) = self.get(neighbor, channel).filter(|&light| light > 0) else {
+                    continue;
+                };
+
+                let lit_by_us =
+                    if channel == Channel::Sun && face == BlockFace::Bottom && old == MAX_LIGHT {
+                        light == MAX_LIGHT
+                    } else {
+                        light < old
+                    };
+
+                if lit_by_us {
+                    // Sources at least as bright as it was lit keep lighting everything around
+                    // them, like the blocks under open sky below a removed chunk
+                    let emission = self.emission(neighbor, channel);
+                    if emission >= light {
+                        relight.push(neighbor);
+                        continue;
+                    }
+
+                    self.set(neighbor, channel, emission);
+                    queue.push((neighbor, light));
+
+                    if emission > 0 {
+                        relight.push(neighbor);
+                    }
+                } else {
+                    relight.push(neighbor);
+                }
+            }
+        }
+
+        self.propagate(channel, relight);
+    }
+
+    fn get(&self, pos: BlockPos, channel: Channel) -> Option<u8> {
+        let light = self.lights.get(&pos.chunk_pos())?;
+        let (x, y, z) = pos.relative_pos();
+        let light = light.read().get(ChunkIndex::new(x, y, z)).get(channel);
+        Some(light)
+    }
+
+    fn set(&mut self, pos: BlockPos, channel: Channel, value: u8) {
+        let Some(light) = self.lights.get(&pos.chunk_pos()) else {
+            return;
+        };
+
+        let (x, y, z) = pos.relative_pos();
+        let mut light = light.write();
+        let light = light.get_mut(ChunkIndex::new(x, y, z));
+        if light.get(channel) == value {
+            return;
+        }
+        light.set(channel, value);
+
+        for pos in std::iter::once(pos).chain(neighbors(pos).map(|(_, pos)| pos)) {
+            let (x, y, z) = pos.relative_pos();
+            *self.changed.entry(pos.chunk_pos()).or_default() |=
+                ChunkSections::single(ChunkSections::section_of(x, y, z));
+        }
+    }
+
+    fn block(&self, pos: BlockPos) -> Option<Block> {
+        let chunk = self.chunks.get(&pos.chunk_pos())?;
+        let (x, y, z) = pos.relative_pos();
+        let block = chunk.read().block(ChunkIndex::new(x, y, z));
+        block
+    }
+
+    fn transmits(&self, pos: BlockPos) -> bool {
+        transmits(self.block(pos), self.models)
+    }
+
+    /// The light a block gives off in a channel, which for sunlight is all of it where the block
+    /// is open to the sky.
+    fn emission(&self, pos: BlockPos, channel: Channel) -> u8 {
+        if channel == Channel::Sun {
+            return if self.open_to_sky(pos) { MAX_LIGHT } else { 0 };
+        }
+
+        let Some(block) = self.block(pos) else {
+            return 0;
+        };
+
+        let [red, green, blue] = block.light_color();
+        match channel {
+            Channel::Sun => 0,
+            Channel::Red => red,
+            Channel::Green => green,
+            Channel::Blue => blue,
+        }
+    }
+}
+
+const PADDED_SIZE: usize = CHUNK_SIZE + 2;
+
+/// The light around the blocks of a chunk while it's being meshed, padded by one block on every
+/// side like [`Occluders`](crate::ambient_occlusion::Occluders), so faces on the chunk boundary
+/// can be lit by the blocks in front of them.
+pub struct LightSampler(Vec<Light>);
+
+impl LightSampler {
+    /// Takes the light of the chunk and its neighbors in the order of
+    /// [`ChunkPos::neighborhood`]. Chunks without light are treated as open sky.
+    pub fn new(neighborhood: &[Option<ChunkLight>; 27]) -> Self {
+        let mut lights = vec![Light::SKY; PADDED_SIZE * PADDED_SIZE * PADDED_SIZE];
+
+        let range = |offset: i32| match offset {
+            -1 => CHUNK_SIZE - 1..CHUNK_SIZE,
+            0 => 0..CHUNK_SIZE,
+            _ => 0..1,
+        };
+
+        let padded =
+            |local: usize, offset: i32| (local as i32 + offset * CHUNK_SIZE as i32 + 1) as usize;
+
+        for (offset, light) in ChunkPos::ZERO.neighborhood().iter().zip(neighborhood) {
+            let Some(light) = light else {
+                continue;
+            };
+
+            let light = light.read();
+
+            for ((x, y), z) in range(offset.x)
+                .cartesian_product(range(offset.y))
+                .cartesian_product(range(offset.z))
+            {
+                let index = Self::index(
+                    padded(x, offset.x),
+                    padded(y, offset.y),
+                    padded(z, offset.z),
+                );
+                lights[index] = light.get(ChunkIndex::new(x, y, z));
+            }
+        }
+
+        Self(lights)
+    }
+
+    /// The light for a chunk downsampled by `scale`, where every cube of blocks gets the
+    /// brightest light in it, so faces of a cube are lit by the cube in front of them. The
+    /// padding is a single block deep, so it's only combined along the sides.
+    pub fn downsampled(&self, scale: usize) -> Self {
+        let cells = [0..1]
+            .into_iter()
+            .chain(
+                (1..=CHUNK_SIZE)
+                    .step_by(scale)
+                    .map(|start| start..start + scale),
+            )
+            .chain([PADDED_SIZE - 1..PADDED_SIZE])
+            .collect_vec();
+
+        let mut lights = self.0.clone();
+        for ((cell_x, cell_y), cell_z) in cells
+            .iter()
+            .cartesian_product(&cells)
+            .cartesian_product(&cells)
+        {
+            let blocks = || {
+                cell_x
+                    .clone()
+                    .cartesian_product(cell_y.clone())
+                    .cartesian_product(cell_z.clone())
+            };
+            let brightest = blocks()
+                .map(|((x, y), z)| self.0[Self::index(x, y, z)])
+                .fold(Light::default(), Light::brightest);
+            for ((x, y), z) in blocks() {
+                lights[Self::index(x, y, z)] = brightest;
+            }
+        }
+
+        Self(lights)
+    }
+
+    /// The light of the block at the given position in the chunk.
+    pub fn block(&self, x: usize, y: usize, z: usize) -> Light {
+        self.0[Self::index(x + 1, y + 1, z + 1)]
+    }
+
+    /// The light in front of a face of the block at the given position in the chunk.
+    pub fn face(&self, x: usize, y: usize, z: usize, face: BlockFace) -> Light {
+        let (x, y, z) = (x + 1, y + 1, z + 1);
+        let (x, y, z) = match face {
+            BlockFace::Left => (x - 1, y, z),
+            BlockFace::Right => (x + 1, y, z),
+            BlockFace::Top => (x, y + 1, z),
+            BlockFace::Bottom => (x, y - 1, z),
+            BlockFace::Front => (x, y, z + 1),
+            BlockFace::Back => (x, y, z - 1),
+        };
+        self.0[Self::index(x, y, z)]
+    }
+
+    fn index(x: usize, y: usize, z: usize) -> usize {
+        (x * PADDED_SIZE + y) * PADDED_SIZE + z
+    }
+}
+
+/// The blocks on the sides of a chunk.
+fn border_blocks() -> impl Iterator<Item = (usize, usize, usize)> {
+    iter_blocks().filter(|&(x, y, z)| [x, y, z].iter().any(|&v| v == 0 || v == CHUNK_SIZE - 1))
+}
+
+fn neighbors(pos: BlockPos) -> impl Iterator<Item = (BlockFace, BlockPos)> {
+    BlockFace::ALL.into_iter().zip([
+        pos + BlockPos::NEG_X,
+        pos + BlockPos::X,
+        pos + BlockPos::Y,
+        pos + BlockPos::NEG_Y,
+        pos + BlockPos::Z,
+        pos + BlockPos::NEG_Z,
+    ])
+}
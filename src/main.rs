@@ -1,46 +1,31 @@
-#![allow(dead_code)]
-#![allow(clippy::type_complexity)]
-#![allow(clippy::too_many_arguments)]
-
 use std::time::Duration;
 
 use bevy::{
     diagnostic::FrameTimeDiagnosticsPlugin, math::DVec3, pbr::ExtendedMaterial, prelude::*,
     window::WindowResolution,
 };
-
-mod block;
-mod chunk;
-mod chunk_material;
-mod egui_menu;
-mod level;
-mod level_generator;
-mod mesh_builder;
-mod player;
-mod plugins;
-mod voxel;
-
 use bevy_egui::EguiPlugin;
 use bevy_xpbd_3d::prelude::*;
 use big_space::{
     bevy_xpbd::floating_origin_sync::FloatingOriginSyncPlugin, debug::FloatingOriginDebugPlugin,
     FloatingOriginPlugin, FloatingOriginSettings,
 };
-use chunk_material::ChunkMaterial;
-use egui_menu::EguiMenuPlugin;
-use level::LevelPlugin;
-use player::PlayerPlugin;
-use plugins::asset_loader::AssetLoaderPlugin;
-use voxel::chunk::CHUNK_SIZE;
-
-#[derive(States, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum GameState {
-    #[default]
-    LoadingAssets,
-    InGame,
-}
+use game::{
+    chunk_diagnostics::ChunkDiagnosticsPlugin, chunk_material::ChunkMaterial,
+    day_cycle::DayCyclePlugin, egui_menu::EguiMenuPlugin, level::LevelPlugin, player::PlayerPlugin,
+    plugins::asset_loader::AssetLoaderPlugin, voxel::chunk::CHUNK_SIZE, world_generator, GameState,
+};
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).is_some_and(|command| command == "new") {
+        if let Err(error) = world_generator::new_world(&args[2..]) {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let window = Window {
         title: "Voxel Game".into(),
         resolution: WindowResolution::new(1400.0, 800.0),
@@ -59,6 +44,7 @@ fn main() {
                 .set(ImagePlugin::default_nearest()),
             FloatingOriginDebugPlugin::<i32>::default(),
             FrameTimeDiagnosticsPlugin,
+            ChunkDiagnosticsPlugin,
             MaterialPlugin::<ExtendedMaterial<StandardMaterial, ChunkMaterial>>::default(),
             FloatingOriginPlugin::<i32>::default(),
             PhysicsPlugins::default().build().disable::<SyncPlugin>(),
@@ -67,16 +53,12 @@ fn main() {
             EguiPlugin,
             LevelPlugin,
             PlayerPlugin,
+            DayCyclePlugin,
             EguiMenuPlugin,
         ))
         .add_state::<GameState>()
         .insert_resource(FloatingOriginSettings::new(CHUNK_SIZE as f32, 0.0))
-        .insert_resource(ClearColor(Color::rgb(0.2, 0.5, 0.8)))
         .insert_resource(Gravity(DVec3::NEG_Y * 26.0))
-        .insert_resource(AmbientLight {
-            color: Color::WHITE,
-            brightness: 1.0,
-        })
         .insert_resource(PrepareConfig {
             position_to_transform: false,
             transform_to_position: true,
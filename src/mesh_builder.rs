@@ -2,18 +2,45 @@ use bevy::{
     prelude::*,
     render::{mesh, render_resource::PrimitiveTopology},
 };
+use num_traits::ToPrimitive;
 
-use crate::chunk_material::ATTRIBUTE_INDEX;
+use crate::{
+    block::BlockFace, block_definitions::FaceTexture, chunk_material::ATTRIBUTE_PACKED,
+    light::Light,
+};
+
+/// Vertex positions are stored in sixteenths of a block, the smallest step block models use.
+const POSITION_SCALE: f32 = 16.0;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Index(u32);
 
+/// The kind of surface a vertex belongs to. The shader picks the normal and the way the texture
+/// is projected onto the surface from this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Surface {
+    Face(BlockFace),
+    /// The diagonal quads of a plant, lit as if they were facing up.
+    Cross,
+}
+
+impl Surface {
+    fn to_bits(self) -> u32 {
+        match self {
+            Self::Face(face) => face.to_u32().unwrap(),
+            Self::Cross => 6,
+        }
+    }
+}
+
+/// Builds chunk meshes, with every vertex packed into two integers:
+///
+/// - position in sixteenths of a block, 10 bits per axis, followed by 2 bits of ambient occlusion
+/// - surface in 3 bits, the [`FaceTexture`] in 13 bits, followed by 4 bits each of sunlight and
+///   red, green and blue block light
 #[derive(Default)]
 pub struct MeshBuilder {
-    positions: Vec<[f32; 3]>,
-    normals: Vec<[f32; 3]>,
-    texcoords: Vec<[f32; 2]>,
-    texindices: Vec<u32>,
+    vertices: Vec<[u32; 2]>,
     indices: Vec<u32>,
 }
 
@@ -22,12 +49,22 @@ impl MeshBuilder {
         Self::default()
     }
 
-    pub fn vertex(&mut self, position: Vec3, normal: Vec3, texcoord: Vec2, texindex: u32) -> Index {
-        let index = self.positions.len();
-        self.positions.push(position.to_array());
-        self.normals.push(normal.to_array());
-        self.texcoords.push(texcoord.to_array());
-        self.texindices.push(texindex);
+    pub fn vertex(
+        &mut self,
+        position: Vec3,
+        surface: Surface,
+        texture: FaceTexture,
+        occlusion: u8,
+        light: Light,
+    ) -> Index {
+        let index = self.vertices.len();
+        let [x, y, z] = (position * POSITION_SCALE).round().as_uvec3().to_array();
+        debug_assert!(x < 1024 && y < 1024 && z < 1024);
+
+        self.vertices.push([
+            x | y << 10 | z << 20 | (occlusion as u32) << 30,
+            surface.to_bits() | texture.to_bits() << 3 | light.to_bits() << 16,
+        ]);
         Index(index as u32)
     }
 
@@ -37,10 +74,7 @@ impl MeshBuilder {
 
     pub fn build(self) -> Mesh {
         let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.texcoords);
-        mesh.insert_attribute(ATTRIBUTE_INDEX, self.texindices);
+        mesh.insert_attribute(ATTRIBUTE_PACKED, self.vertices);
         mesh.set_indices(Some(mesh::Indices::U32(self.indices)));
         mesh
     }
@@ -2,18 +2,24 @@ use std::{f32::consts::PI as PI_32, f64::consts::PI as PI_64};
 
 use bevy::{
     ecs::event::ManualEventReader,
-    input::mouse::MouseMotion,
+    input::mouse::{MouseMotion, MouseWheel},
     math::{DQuat, DVec3},
     prelude::*,
     window::{CursorGrabMode, PrimaryWindow},
 };
 use bevy_xpbd_3d::prelude::*;
 use big_space::{FloatingOrigin, GridCell};
+use itertools::Itertools;
+use num_traits::FromPrimitive;
 
 use crate::{
-    block::Block,
+    block::{Block, BlockFace},
+    block_definitions::BlockModels,
     level::{Dirty, Level},
-    voxel::{block_pos::BlockPos, chunk::CHUNK_SIZE, chunk_index::ChunkIndex, chunk_pos::ChunkPos},
+    voxel::{
+        block_pos::BlockPos, chunk::CHUNK_SIZE, chunk_index::ChunkIndex, chunk_pos::ChunkPos,
+        chunk_sections::ChunkSections,
+    },
     GameState,
 };
 
@@ -27,10 +33,12 @@ impl Plugin for PlayerPlugin {
             .init_resource::<JumpHeight>()
             .init_resource::<MouseSensitivity>()
             .init_resource::<Reach>()
+            .init_resource::<SelectedBlock>()
             .add_systems(Startup, (setup_player, setup_input))
             .add_systems(
                 Update,
-                (break_block, toggle_grab_cursor, update_fog).run_if(in_state(GameState::InGame)),
+                (break_block, select_block, toggle_grab_cursor, update_fog)
+                    .run_if(in_state(GameState::InGame)),
             )
             .add_systems(
                 FixedUpdate,
@@ -89,6 +97,15 @@ impl Default for Reach {
     }
 }
 
+#[derive(Resource)]
+pub struct SelectedBlock(pub Block);
+
+impl Default for SelectedBlock {
+    fn default() -> Self {
+        Self(Block::Sand)
+    }
+}
+
 #[derive(Component)]
 pub struct Player;
 
@@ -131,10 +148,8 @@ fn setup_player(mut commands: Commands) {
                     transform: Transform::from_xyz(0.0, 0.7, 0.0),
                     ..default()
                 },
-                FogSettings {
-                    color: Color::rgba(0.2, 0.5, 0.8, 1.0),
-                    ..default()
-                },
+                // Colored by the time of day
+                FogSettings::default(),
             ));
         });
 }
@@ -143,7 +158,9 @@ fn break_block(
     mut commands: Commands,
     spatial_query: SpatialQuery,
     level: Res<Level>,
+    models: Res<BlockModels>,
     reach: Res<Reach>,
+    selected_block: Res<SelectedBlock>,
     mouse: Res<Input<MouseButton>>,
     player: Query<(Entity, &GridCell<i32>), With<Player>>,
     camera: Query<&GlobalTransform, With<PlayerCamera>>,
@@ -169,7 +186,7 @@ fn break_block(
     } else if mouse.just_pressed(MouseButton::Right) {
         let hit_pos = global_transform.translation()
             + global_transform.forward() * (hit.time_of_impact - 0.01) as f32;
-        (hit_pos, Some(Block::Sand))
+        (hit_pos, Some(selected_block.0))
     } else {
         return;
     };
@@ -184,8 +201,9 @@ fn break_block(
     };
 
     let pos = block_pos.relative_pos();
+    let index = ChunkIndex::new(pos.0, pos.1, pos.2);
     let mut chunk = chunk.write();
-    let block_mut = chunk.block_mut(ChunkIndex::new(pos.0, pos.1, pos.2));
+    let block_mut = chunk.block_mut(index);
 
     if block.is_none() {
         *block_mut = None;
@@ -195,17 +213,74 @@ fn break_block(
         return;
     }
 
+    *chunk.facing_mut(index) = block.map_or_else(BlockFace::default, |block| {
+        let clicked = BlockFace::from_direction(hit.normal.as_vec3());
+        block.placement_facing(clicked, global_transform.forward())
+    });
+
     drop(chunk);
+    level.save_chunk(chunk_pos);
 
-    for pos in [vec![chunk_pos], chunk_pos.adjacent().to_vec()]
-        .iter()
-        .flatten()
-    {
-        let Some((entity, _)) = chunks.iter().find(|c| c.1 == pos) else {
+    // Only the faces of the block and of the blocks around it can change, along with the ones
+    // the light changed for, and the sections they belong to may be in other chunks.
+    let mut light_engine = level.light_engine(&models);
+    let sky_changed = light_engine.update_block(block_pos);
+    let mut sections = light_engine.changed();
+
+    if sky_changed {
+        level.save_sky(chunk_pos.column());
+    }
+
+    for ((x, y), z) in (-1..=1).cartesian_product(-1..=1).cartesian_product(-1..=1) {
+        let neighbor = block_pos + BlockPos::new(x, y, z);
+        let (x, y, z) = neighbor.relative_pos();
+        *sections.entry(neighbor.chunk_pos()).or_default() |=
+            ChunkSections::single(ChunkSections::section_of(x, y, z));
+    }
+
+    // Blocks in the chunks on the other sides of the block may connect to it, such as fences,
+    // so their colliders are rebuilt too.
+    let touching = [
+        BlockPos::NEG_X,
+        BlockPos::X,
+        BlockPos::Y,
+        BlockPos::NEG_Y,
+        BlockPos::Z,
+        BlockPos::NEG_Z,
+    ]
+    .map(|offset| (block_pos + offset).chunk_pos());
+
+    for (entity, pos) in chunks.iter() {
+        let Some(&sections) = sections.get(pos) else {
+            continue;
+        };
+
+        let dirty = Dirty {
+            sections,
+            blocks: *pos == chunk_pos || touching.contains(pos),
+        };
+        dirty.mark(&mut commands, entity);
+    }
+}
+
+fn select_block(mut scroll: EventReader<MouseWheel>, mut selected_block: ResMut<SelectedBlock>) {
+    let blocks: Vec<Block> = (0..=u8::MAX).map_while(Block::from_u8).collect();
+
+    for event in scroll.read() {
+        let index = blocks
+            .iter()
+            .position(|&block| block == selected_block.0)
+            .unwrap_or_default();
+
+        let offset = if event.y < 0.0 {
+            1
+        } else if event.y > 0.0 {
+            blocks.len() - 1
+        } else {
             continue;
         };
 
-        commands.entity(entity).insert(Dirty);
+        selected_block.0 = blocks[(index + offset) % blocks.len()];
     }
 }
 
@@ -2,56 +2,118 @@ use bevy::{
     prelude::*,
     render::render_resource::{Extent3d, TextureDimension, TextureFormat},
 };
-use indexmap::IndexMap;
 
-use crate::GameState;
+use crate::{
+    block_definitions::{BlockDefinitions, BlockDefinitionsLoader, BlockModels, BlockTextures},
+    GameState,
+};
 
 pub struct AssetLoaderPlugin;
 
 impl Plugin for AssetLoaderPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::LoadingAssets), load_assets)
-            .add_systems(
-                Update,
-                check_assets.run_if(in_state(GameState::LoadingAssets)),
-            );
+        app.init_asset::<BlockDefinitions>()
+            .init_asset_loader::<BlockDefinitionsLoader>()
+            .add_systems(OnEnter(GameState::LoadingAssets), load_assets)
+            .add_systems(Update, (load_block_textures, check_assets).chain());
     }
 }
 
 #[derive(Resource, Deref)]
 pub struct BlockArray(Handle<Image>);
 
+/// The image of every layer in the block array, in order, along with the textures and models of
+/// the block definitions they were loaded for. Those refer to the layers of this array, so they
+/// only replace the ones in use once it's built.
+#[derive(Resource)]
+pub struct Blocks {
+    images: Vec<Handle<Image>>,
+    textures: BlockTextures,
+    models: BlockModels,
+}
+
 #[derive(Resource)]
-pub struct Blocks(IndexMap<String, Handle<Image>>);
+struct BlockDefinitionsHandle(Handle<BlockDefinitions>);
 
 fn load_assets(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let mut blocks = IndexMap::new();
+    commands.insert_resource(BlockDefinitionsHandle(
+        asset_server.load("blocks/definitions.blocks.ron"),
+    ));
+}
 
-    blocks.insert("dirt".into(), asset_server.load("blocks/dirt.png"));
-    blocks.insert(
-        "grass_side".into(),
-        asset_server.load("blocks/grass_side.png"),
-    );
-    blocks.insert(
-        "grass_top".into(),
-        asset_server.load("blocks/grass_top.png"),
-    );
-    blocks.insert("rock".into(), asset_server.load("blocks/rock.png"));
-    blocks.insert("sand".into(), asset_server.load("blocks/sand.png"));
+/// Loads the textures and models used by the block definitions, whenever they're first loaded or
+/// edited.
+fn load_block_textures(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<BlockDefinitions>>,
+    handle: Res<BlockDefinitionsHandle>,
+    definitions: Res<Assets<BlockDefinitions>>,
+    asset_server: Res<AssetServer>,
+) {
+    let changed = events.read().any(|event| match event {
+        AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => {
+            *id == handle.0.id()
+        }
+        _ => false,
+    });
+
+    if !changed {
+        return;
+    }
+
+    let Some(definitions) = definitions.get(&handle.0) else {
+        return;
+    };
+
+    let images = definitions
+        .texture_names()
+        .into_iter()
+        .map(|name| asset_server.load(format!("blocks/{name}.png")))
+        .collect();
 
-    commands.insert_resource(Blocks(blocks));
+    commands.insert_resource(Blocks {
+        images,
+        textures: BlockTextures::new(definitions),
+        models: BlockModels::new(definitions),
+    });
 }
 
+/// Builds the block array once every texture is loaded, and rebuilds it in place when any of
+/// them change so that the materials using it don't need a new handle. The textures and models
+/// of new block definitions are swapped in along with their array, which remeshes the chunks.
 fn check_assets(
     mut commands: Commands,
     mut next_state: ResMut<NextState<GameState>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
     mut image_assets: ResMut<Assets<Image>>,
-    blocks: Res<Blocks>,
+    mut pending: Local<bool>,
+    mut pending_definitions: Local<bool>,
+    state: Res<State<GameState>>,
+    block_array: Option<Res<BlockArray>>,
+    blocks: Option<Res<Blocks>>,
     asset_server: Res<AssetServer>,
 ) {
+    let Some(blocks) = blocks else {
+        return;
+    };
+
+    let modified = image_events.read().any(|event| match event {
+        AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => {
+            blocks.images.iter().any(|handle| handle.id() == *id)
+        }
+        _ => false,
+    });
+
+    *pending |= modified || blocks.is_changed();
+    *pending_definitions |= blocks.is_changed();
+
+    if !*pending {
+        return;
+    }
+
     let Some(images) = blocks
-        .0
-        .values()
+        .images
+        .iter()
         .map(|handle| {
             if !asset_server.is_loaded_with_dependencies(handle) {
                 None
@@ -78,8 +140,22 @@ fn check_assets(
         TextureFormat::Rgba8UnormSrgb,
     );
 
-    let handle = image_assets.add(array_texture);
-    commands.insert_resource(BlockArray(handle));
+    *pending = false;
+
+    if let Some(image) = block_array.and_then(|handle| image_assets.get_mut(&handle.0)) {
+        *image = array_texture;
+    } else {
+        let handle = image_assets.add(array_texture);
+        commands.insert_resource(BlockArray(handle));
+    }
 
-    next_state.set(GameState::InGame);
+    if *pending_definitions {
+        *pending_definitions = false;
+        commands.insert_resource(blocks.textures.clone());
+        commands.insert_resource(blocks.models.clone());
+    }
+
+    if *state.get() == GameState::LoadingAssets {
+        next_state.set(GameState::InGame);
+    }
 }
@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::{
+    block_definitions::BlockModels,
+    voxel::{block_pos::BlockPos, chunk::CHUNK_SIZE, chunk_pos::ChunkPos},
+    world_generator::WorldGenerator,
+};
+
+/// The highest block that may keep sunlight from the blocks below it, for every column of blocks
+/// in a column of chunks. Everything above it is open to the sky, whether or not the chunks up
+/// there are loaded.
+///
+/// It's the highest block that stops light where that's known, and otherwise the top of the
+/// highest chunk below the last known one that hasn't been loaded since, which is looked through
+/// once it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkyMap(Vec<i64>);
+
+pub type ChunkSky = Arc<RwLock<SkyMap>>;
+
+impl SkyMap {
+    /// A column with nothing in it, open to the sky all the way down.
+    pub const OPEN: i64 = i64::MIN;
+
+    /// Builds the map from the top of every column, given by its position in the chunk.
+    pub fn new(mut top: impl FnMut(usize, usize) -> i64) -> Self {
+        let mut tops = Vec::with_capacity(CHUNK_SIZE * CHUNK_SIZE);
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                tops.push(top(x, z));
+            }
+        }
+        Self(tops)
+    }
+
+    /// The top of the column the block is in.
+    pub fn top(&self, pos: BlockPos) -> i64 {
+        self.0[Self::index(pos)]
+    }
+
+    pub fn set_top(&mut self, pos: BlockPos, top: i64) {
+        self.0[Self::index(pos)] = top;
+    }
+
+    /// Reads the map of a column of chunks that was changed in a world, or makes it from the
+    /// generator if it never was.
+    pub fn load(
+        connection: &Connection,
+        generator: &dyn WorldGenerator,
+        models: &BlockModels,
+        column: ChunkPos,
+    ) -> rusqlite::Result<Self> {
+        let data: Option<Vec<u8>> = connection
+            .query_row(
+                "SELECT data FROM sky_maps WHERE x = ? AND z = ?",
+                (column.x, column.z),
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(data
+            .and_then(|data| Self::deserialize(&data))
+            .unwrap_or_else(|| generator.generate_sky_map(column, models)))
+    }
+
+    pub fn save(&self, connection: &Connection, column: ChunkPos) -> rusqlite::Result<()> {
+        connection.execute(
+            "INSERT OR REPLACE INTO sky_maps (x, z, data) VALUES (?1, ?2, ?3)",
+            (column.x, column.z, self.serialize()),
+        )?;
+        Ok(())
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|top| top.to_le_bytes()).collect()
+    }
+
+    fn deserialize(data: &[u8]) -> Option<Self> {
+        if data.len() != CHUNK_SIZE * CHUNK_SIZE * 8 {
+            return None;
+        }
+
+        let tops = data
+            .chunks_exact(8)
+            .map(|bytes| i64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        Some(Self(tops))
+    }
+
+    fn index(pos: BlockPos) -> usize {
+        let (x, _, z) = pos.relative_pos();
+        x * CHUNK_SIZE + z
+    }
+}
@@ -3,3 +3,4 @@ pub mod chunk;
 pub mod chunk_data;
 pub mod chunk_index;
 pub mod chunk_pos;
+pub mod chunk_sections;
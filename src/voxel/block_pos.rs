@@ -32,6 +32,17 @@ impl BlockPos {
         )
     }
 
+    /// A hash of the position that's stable across runs, for picking per block variations.
+    pub fn hash_u32(self) -> u32 {
+        let mut hash = (self.x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (self.y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+            ^ (self.z as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+        hash ^= hash >> 31;
+        hash = hash.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        hash ^= hash >> 29;
+        hash as u32
+    }
+
     pub fn relative_pos(self) -> (usize, usize, usize) {
         (
             self.x.rem_euclid(CHUNK_SIZE as i64) as usize,
@@ -1,12 +1,20 @@
+use itertools::Itertools;
 use num_traits::{FromPrimitive, ToPrimitive};
 
-use crate::block::Block;
+use crate::{
+    block::{Block, BlockFace},
+    block_definitions::BlockModels,
+    block_model::CUBE,
+};
 
 use super::{chunk::CHUNK_SIZE, chunk_index::ChunkIndex};
 
+const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
 #[derive(Clone)]
 pub struct ChunkData {
     blocks: Vec<Option<Block>>,
+    facings: Vec<BlockFace>,
 }
 
 impl ChunkData {
@@ -18,72 +26,115 @@ impl ChunkData {
         &mut self.blocks[index.as_usize()]
     }
 
-    pub fn serialize(&self) -> Vec<u8> {
-        let mut data = Vec::new();
-        let mut last = None;
-        let mut count = 0;
+    /// The direction the block at the given index was placed facing.
+    pub fn facing(&self, index: ChunkIndex) -> BlockFace {
+        self.facings[index.as_usize()]
+    }
 
-        for block in self.blocks.iter() {
-            let block_type: u8 = block
-                .and_then(|block| block.to_u8())
-                .map(|id| id + 1)
-                .unwrap_or_default();
+    pub fn facing_mut(&mut self, index: ChunkIndex) -> &mut BlockFace {
+        &mut self.facings[index.as_usize()]
+    }
 
-            if last == Some(block_type) {
-                count += 1;
-            } else {
-                if let Some(block_type) = last {
-                    data.extend((count as u16).to_be_bytes());
-                    data.push(block_type);
+    /// A coarser copy of the chunk for distant level of detail meshes. Every cube of `scale`
+    /// blocks is filled with its most common full block, as long as at least half of the cube is
+    /// made out of full blocks.
+    pub fn downsampled(&self, scale: usize, models: &BlockModels) -> Self {
+        let mut chunk = Self::default();
+        let mut counts: Vec<(Block, usize)> = Vec::new();
+        let cells = (0..CHUNK_SIZE).step_by(scale);
+
+        for ((x, y), z) in cells
+            .clone()
+            .cartesian_product(cells.clone())
+            .cartesian_product(cells)
+        {
+            counts.clear();
+            for ((dx, dy), dz) in (0..scale)
+                .cartesian_product(0..scale)
+                .cartesian_product(0..scale)
+            {
+                let Some(block) = self.block(ChunkIndex::new(x + dx, y + dy, z + dz)) else {
+                    continue;
+                };
+                if block.model(models) != &CUBE {
+                    continue;
+                }
+                match counts.iter_mut().find(|(other, _)| *other == block) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((block, 1)),
                 }
-                last = Some(block_type);
-                count = 1;
             }
-        }
 
-        if let Some(block_type) = last {
-            if data.is_empty() {
-                data.push(block_type);
-            } else {
-                data.extend((count as u16).to_be_bytes());
-                data.push(block_type);
+            let total: usize = counts.iter().map(|(_, count)| count).sum();
+            if total * 2 < scale * scale * scale {
+                continue;
+            }
+
+            let block = counts
+                .iter()
+                .max_by_key(|(_, count)| count)
+                .map(|(block, _)| *block);
+            for ((dx, dy), dz) in (0..scale)
+                .cartesian_product(0..scale)
+                .cartesian_product(0..scale)
+            {
+                *chunk.block_mut(ChunkIndex::new(x + dx, y + dy, z + dz)) = block;
             }
         }
 
+        chunk
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        let blocks = self.blocks.iter().map(|block| {
+            block
+                .and_then(|block| block.to_u8())
+                .map(|id| id + 1)
+                .unwrap_or_default()
+        });
+
+        // Chunks without oriented blocks are stored exactly as they were before facings existed.
+        if self
+            .facings
+            .iter()
+            .all(|&facing| facing == BlockFace::default())
+        {
+            write_runs(&mut data, blocks, true);
+        } else {
+            write_runs(&mut data, blocks, false);
+            write_runs(
+                &mut data,
+                self.facings.iter().map(|facing| facing.to_u8().unwrap()),
+                false,
+            );
+        }
+
         data
     }
 
     pub fn deserialize(data: &[u8]) -> Option<Self> {
         let mut pos = 0;
         let mut chunk = Self {
-            blocks: Vec::with_capacity(CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE),
+            blocks: Vec::with_capacity(CHUNK_VOLUME),
+            facings: Vec::with_capacity(CHUNK_VOLUME),
         };
 
-        if data.len() == 1 {
-            for _ in 0..chunk.blocks.capacity() {
-                if data[0] == 0 {
-                    chunk.blocks.push(None);
-                } else {
-                    chunk.blocks.push(Some(Block::from_u8(data[0] - 1)?));
-                }
+        for byte in read_runs(data, &mut pos)? {
+            if byte == 0 {
+                chunk.blocks.push(None);
+            } else {
+                chunk.blocks.push(Some(Block::from_u8(byte - 1)?));
             }
-            pos += 1;
         }
 
-        while pos + 2 < data.len() {
-            let count = u16::from_be_bytes([data[pos], data[pos + 1]]);
-            pos += 2;
-
-            for _ in 0..count {
-                let byte = data[pos];
-                if byte == 0 {
-                    chunk.blocks.push(None);
-                } else {
-                    chunk.blocks.push(Some(Block::from_u8(byte - 1)?));
-                }
+        if pos == data.len() {
+            chunk.facings = vec![BlockFace::default(); CHUNK_VOLUME];
+        } else {
+            for byte in read_runs(data, &mut pos)? {
+                chunk.facings.push(BlockFace::from_u8(byte)?);
             }
-
-            pos += 1;
         }
 
         if pos == data.len() {
@@ -97,7 +148,62 @@ impl ChunkData {
 impl Default for ChunkData {
     fn default() -> Self {
         Self {
-            blocks: vec![None; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE],
+            blocks: vec![None; CHUNK_VOLUME],
+            facings: vec![BlockFace::default(); CHUNK_VOLUME],
+        }
+    }
+}
+
+/// Run-length encodes a full chunk worth of values as `[count: u16, value: u8]` pairs.
+/// If `allow_uniform` is set, a chunk made of a single value is stored as just that value.
+fn write_runs(data: &mut Vec<u8>, values: impl Iterator<Item = u8>, allow_uniform: bool) {
+    let start = data.len();
+    let mut last = None;
+    let mut count = 0;
+
+    for value in values {
+        if last == Some(value) {
+            count += 1;
+        } else {
+            if let Some(value) = last {
+                data.extend((count as u16).to_be_bytes());
+                data.push(value);
+            }
+            last = Some(value);
+            count = 1;
         }
     }
+
+    if let Some(value) = last {
+        if allow_uniform && data.len() == start {
+            data.push(value);
+        } else {
+            data.extend((count as u16).to_be_bytes());
+            data.push(value);
+        }
+    }
+}
+
+fn read_runs(data: &[u8], pos: &mut usize) -> Option<Vec<u8>> {
+    let mut values = Vec::with_capacity(CHUNK_VOLUME);
+
+    if data.len() == 1 {
+        values.resize(CHUNK_VOLUME, data[0]);
+        *pos += 1;
+        return Some(values);
+    }
+
+    while values.len() < CHUNK_VOLUME {
+        let count = u16::from_be_bytes([*data.get(*pos)?, *data.get(*pos + 1)?]);
+        let value = *data.get(*pos + 2)?;
+        *pos += 3;
+
+        values.extend(std::iter::repeat(value).take(count as usize));
+    }
+
+    if values.len() == CHUNK_VOLUME {
+        Some(values)
+    } else {
+        None
+    }
 }
@@ -1,3 +1,5 @@
+use crate::block::BlockFace;
+
 use super::chunk::CHUNK_SIZE;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -8,12 +10,37 @@ impl ChunkIndex {
         Self((x + y * CHUNK_SIZE + z * CHUNK_SIZE * CHUNK_SIZE) as u16)
     }
 
+    pub fn xyz(self) -> (usize, usize, usize) {
+        let index = self.as_usize();
+        (
+            index % CHUNK_SIZE,
+            index / CHUNK_SIZE % CHUNK_SIZE,
+            index / (CHUNK_SIZE * CHUNK_SIZE),
+        )
+    }
+
+    /// The index of the block next to this one on the given side, or `None` if that's in another
+    /// chunk.
+    pub fn neighbor(self, face: BlockFace) -> Option<Self> {
+        let (x, y, z) = self.xyz();
+        let last = CHUNK_SIZE - 1;
+        let (x, y, z) = match face {
+            BlockFace::Left => (x.checked_sub(1)?, y, z),
+            BlockFace::Right => ((x < last).then_some(x + 1)?, y, z),
+            BlockFace::Top => (x, (y < last).then_some(y + 1)?, z),
+            BlockFace::Bottom => (x, y.checked_sub(1)?, z),
+            BlockFace::Front => (x, y, (z < last).then_some(z + 1)?),
+            BlockFace::Back => (x, y, z.checked_sub(1)?),
+        };
+        Some(Self::new(x, y, z))
+    }
+
     pub fn iter() -> impl Iterator<Item = Self> {
         let len = CHUNK_SIZE as u16;
         (0..len).map(Self)
     }
 
-    pub(super) fn as_usize(self) -> usize {
+    pub(crate) fn as_usize(self) -> usize {
         self.0 as usize
     }
 }
@@ -15,6 +15,7 @@ pub struct ChunkPos {
 }
 
 impl ChunkPos {
+    pub const ZERO: Self = Self::new(0, 0, 0);
     pub const X: Self = Self::new(1, 0, 0);
     pub const Y: Self = Self::new(0, 1, 0);
     pub const Z: Self = Self::new(0, 0, 1);
@@ -35,6 +36,11 @@ impl ChunkPos {
         )
     }
 
+    /// The chunk at height 0 in the same column, which stands for the whole column.
+    pub fn column(self) -> ChunkPos {
+        ChunkPos::new(self.x, 0, self.z)
+    }
+
     pub fn chunks_within_radius(self, chunk_radius: i32) -> IndexSet<ChunkPos> {
         let radius_squared = chunk_radius * chunk_radius;
         let range = -chunk_radius..=chunk_radius;
@@ -49,7 +55,7 @@ impl ChunkPos {
             .collect()
     }
 
-    fn distance_squared(self, other: Self) -> i32 {
+    pub fn distance_squared(self, other: Self) -> i32 {
         let diff = self - other;
         (diff.x * diff.x) + (diff.y * diff.y) + (diff.z * diff.z)
     }
@@ -88,6 +94,14 @@ impl ChunkPos {
             self.back(),
         ]
     }
+
+    /// This chunk and the 26 surrounding it, ordered by x, then y, then z.
+    pub fn neighborhood(self) -> [ChunkPos; 27] {
+        std::array::from_fn(|i| {
+            let i = i as i32;
+            self + ChunkPos::new(i / 9 - 1, i / 3 % 3 - 1, i % 3 - 1)
+        })
+    }
 }
 
 impl From<GridCell<i32>> for ChunkPos {
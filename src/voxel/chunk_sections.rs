@@ -0,0 +1,113 @@
+use std::ops;
+
+use bevy::prelude::*;
+use itertools::Itertools;
+
+use super::{chunk::CHUNK_SIZE, chunk_pos::ChunkPos};
+
+/// Chunks are meshed in cubes of this size, so that an edit only rebuilds the part around it.
+pub const SECTION_SIZE: usize = 16;
+
+const SECTIONS_PER_AXIS: usize = CHUNK_SIZE / SECTION_SIZE;
+
+pub const SECTION_COUNT: usize = SECTIONS_PER_AXIS * SECTIONS_PER_AXIS * SECTIONS_PER_AXIS;
+
+/// A set of sections of a chunk, indexed by x, then y, then z like blocks are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChunkSections(u8);
+
+impl ChunkSections {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self(u8::MAX >> (8 - SECTION_COUNT));
+
+    /// The section the block at the given position in the chunk belongs to.
+    pub fn section_of(x: usize, y: usize, z: usize) -> usize {
+        let (x, y, z) = (x / SECTION_SIZE, y / SECTION_SIZE, z / SECTION_SIZE);
+        x + (y + z * SECTIONS_PER_AXIS) * SECTIONS_PER_AXIS
+    }
+
+    /// The position of the first block of a section in the chunk.
+    pub fn origin(section: usize) -> UVec3 {
+        let n = SECTIONS_PER_AXIS;
+        UVec3::new(
+            (section % n) as u32,
+            (section / n % n) as u32,
+            (section / (n * n)) as u32,
+        ) * SECTION_SIZE as u32
+    }
+
+    /// The positions of all blocks of a section in the chunk.
+    pub fn blocks(section: usize) -> impl Iterator<Item = (usize, usize, usize)> {
+        let origin = Self::origin(section);
+        let range = |start: u32| start as usize..start as usize + SECTION_SIZE;
+        range(origin.x)
+            .cartesian_product(range(origin.y))
+            .cartesian_product(range(origin.z))
+            .map(|((x, y), z)| (x, y, z))
+    }
+
+    /// The sections on the side, edge or corner of the chunk towards the neighbor at the given
+    /// offset. All of them for an offset of zero.
+    pub fn bordering(offset: ChunkPos) -> Self {
+        let touches = |offset: i32, origin: u32| match offset.signum() {
+            -1 => origin == 0,
+            1 => origin as usize == CHUNK_SIZE - SECTION_SIZE,
+            _ => true,
+        };
+
+        (0..SECTION_COUNT)
+            .filter(|&section| {
+                let origin = Self::origin(section);
+                touches(offset.x, origin.x)
+                    && touches(offset.y, origin.y)
+                    && touches(offset.z, origin.z)
+            })
+            .collect()
+    }
+
+    pub fn single(section: usize) -> Self {
+        Self(1 << section)
+    }
+
+    pub fn contains(self, section: usize) -> bool {
+        self.0 & (1 << section) != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = usize> {
+        (0..SECTION_COUNT).filter(move |&section| self.contains(section))
+    }
+}
+
+impl FromIterator<usize> for ChunkSections {
+    fn from_iter<T: IntoIterator<Item = usize>>(iter: T) -> Self {
+        iter.into_iter().fold(Self::NONE, |sections, section| {
+            sections | Self::single(section)
+        })
+    }
+}
+
+impl ops::BitOr for ChunkSections {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl ops::BitOrAssign for ChunkSections {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl ops::Sub for ChunkSections {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self(self.0 & !rhs.0)
+    }
+}
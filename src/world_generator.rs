@@ -0,0 +1,294 @@
+//! The generators that fill chunks that were never saved, and the world metadata that picks
+//! which one a world uses.
+//!
+//! A new world uses noise terrain. To make another kind of world, run `game new <generator>`
+//! before any chunks are generated and then start the game, for example for a superflat world:
+//!
+//! ```text
+//! game new "Flat(layers: [(block: Rock, thickness: 60), (block: Dirt, thickness: 3), (block: Grass, thickness: 1)])"
+//! ```
+
+use std::{error::Error, sync::Arc};
+
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block::Block,
+    block_definitions::BlockModels,
+    level::{open_database, DATABASE},
+    level_generator::LevelGenerator,
+    light::transmits,
+    sky_map::SkyMap,
+    voxel::{
+        block_pos::BlockPos,
+        chunk::{iter_blocks, CHUNK_SIZE},
+        chunk_data::ChunkData,
+        chunk_index::ChunkIndex,
+        chunk_pos::ChunkPos,
+    },
+};
+
+/// The key of the generator preset in the metadata table.
+const GENERATOR_KEY: &str = "generator";
+
+const USAGE: &str =
+    "usage: game new <generator>, such as Void, Checkerboard or \"Noise(seed: 42)\"";
+
+/// Stores the generator given on the command line in the world database, so the level is set up
+/// with it once the game starts.
+pub fn new_world(args: &[String]) -> Result<(), Box<dyn Error>> {
+    // Presets with spaces in them may have been split up by the shell.
+    let text = args.join(" ");
+    if text.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let preset: GeneratorPreset = ron::from_str(&text)
+        .map_err(|error| format!("invalid generator {text:?}: {error}\n{USAGE}"))?;
+
+    preset.store(&open_database(DATABASE)?)
+}
+
+/// Fills in chunks. Chunks are generated on background tasks, so generators are shared between
+/// threads, and have to give the same chunk every time for the same position. They're handed the
+/// block models, which tell them which blocks are solid.
+pub trait WorldGenerator: Send + Sync {
+    fn generate_chunk(&self, pos: ChunkPos, models: &BlockModels) -> ChunkData;
+
+    /// The highest block that stops sunlight in every column of the chunk's column, as it's
+    /// generated, so chunks can be lit without the ones above them.
+    fn generate_sky_map(&self, pos: ChunkPos, models: &BlockModels) -> SkyMap;
+
+    /// The name of the biome at a position, for generators that have biomes.
+    fn biome(&self, _x: i64, _z: i64) -> Option<&str> {
+        None
+    }
+}
+
+/// Which generator a world uses and how it's set up, stored in the world's metadata.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GeneratorPreset {
+    /// Terrain shaped by noise, with biomes, caves and features.
+    Noise { seed: u64 },
+    /// Flat layers of blocks, stacked up from height 0.
+    Flat { layers: Vec<FlatLayer> },
+    /// Nothing at all.
+    Void,
+    /// Solid and empty chunks alternating like a checkerboard, for checking what happens at chunk
+    /// borders.
+    Checkerboard,
+}
+
+impl Default for GeneratorPreset {
+    fn default() -> Self {
+        Self::Noise { seed: 0 }
+    }
+}
+
+impl GeneratorPreset {
+    pub fn build(&self) -> Arc<dyn WorldGenerator> {
+        match self {
+            Self::Noise { seed } => Arc::new(LevelGenerator::new(*seed)),
+            Self::Flat { layers } => Arc::new(FlatGenerator::new(layers.clone())),
+            Self::Void => Arc::new(VoidGenerator),
+            Self::Checkerboard => Arc::new(CheckerboardGenerator),
+        }
+    }
+
+    /// Reads the preset from the world's metadata, or stores the default for a new world.
+    pub fn load_or_create(connection: &Connection) -> Result<Self, Box<dyn Error>> {
+        Self::create_table(connection)?;
+
+        if let Some(preset) = Self::read(connection)? {
+            return Ok(preset);
+        }
+
+        let preset = Self::default();
+        let value = ron::to_string(&preset).expect("generator presets can be serialized");
+        connection.execute(
+            "INSERT OR IGNORE INTO metadata (key, value) VALUES (?, ?)",
+            (GENERATOR_KEY, value),
+        )?;
+        Ok(preset)
+    }
+
+    /// Makes this the preset of a world. Chunks that were already generated wouldn't match the
+    /// ones the new generator makes around them, so worlds with any chunks can't switch presets.
+    pub fn store(&self, connection: &Connection) -> Result<(), Box<dyn Error>> {
+        Self::create_table(connection)?;
+
+        let has_chunks: bool =
+            connection.query_row("SELECT EXISTS (SELECT 1 FROM chunks)", (), |row| row.get(0))?;
+        if has_chunks {
+            let current = Self::read(connection)?.unwrap_or_default();
+            if current == *self {
+                return Ok(());
+            }
+            return Err(format!(
+                "the world already has chunks generated by {current:?}, so it can't switch to \
+                 another generator"
+            )
+            .into());
+        }
+
+        let value = ron::to_string(self)?;
+        connection.execute(
+            "INSERT OR REPLACE INTO metadata (key, value) VALUES (?, ?)",
+            (GENERATOR_KEY, value),
+        )?;
+        Ok(())
+    }
+
+    fn create_table(connection: &Connection) -> rusqlite::Result<()> {
+        connection.execute(
+            "
+            CREATE TABLE IF NOT EXISTS metadata (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )
+            ",
+            (),
+        )?;
+        Ok(())
+    }
+
+    /// Reads the preset from the world's metadata without changing the database. Worlds from
+    /// before there was any metadata don't have one. A preset that can't be read is an error
+    /// rather than a reason to fall back to the default, which would generate the rest of the
+    /// world with the wrong terrain.
+    pub fn read(connection: &Connection) -> Result<Option<Self>, Box<dyn Error>> {
+        let has_metadata: bool = connection.query_row(
+            "
+            SELECT EXISTS (
+                SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'metadata'
+            )
+            ",
+            (),
+            |row| row.get(0),
+        )?;
+        if !has_metadata {
+            return Ok(None);
+        }
+
+        let value: Option<String> = connection
+            .query_row(
+                "SELECT value FROM metadata WHERE key = ?",
+                (GENERATOR_KEY,),
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        let Some(value) = value else {
+            return Ok(None);
+        };
+
+        let preset = ron::from_str(&value)
+            .map_err(|error| format!("invalid world generator {value:?}: {error}"))?;
+        Ok(Some(preset))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FlatLayer {
+    pub block: Block,
+    pub thickness: u32,
+}
+
+/// Flat layers of blocks with nothing below them, for building on.
+pub struct FlatGenerator {
+    /// The block at each height, starting from 0.
+    blocks: Vec<Block>,
+}
+
+impl FlatGenerator {
+    pub fn new(layers: Vec<FlatLayer>) -> Self {
+        let blocks = layers
+            .iter()
+            .flat_map(|layer| std::iter::repeat(layer.block).take(layer.thickness as usize))
+            .collect();
+        Self { blocks }
+    }
+}
+
+impl WorldGenerator for FlatGenerator {
+    fn generate_chunk(&self, pos: ChunkPos, _models: &BlockModels) -> ChunkData {
+        let mut chunk = ChunkData::default();
+        for (x, y, z) in iter_blocks() {
+            let height = pos.block_pos().y + y as i64;
+            let block = usize::try_from(height)
+                .ok()
+                .and_then(|height| self.blocks.get(height));
+            *chunk.block_mut(ChunkIndex::new(x, y, z)) = block.copied();
+        }
+        chunk
+    }
+
+    fn generate_sky_map(&self, _pos: ChunkPos, models: &BlockModels) -> SkyMap {
+        let top = self
+            .blocks
+            .iter()
+            .rposition(|&block| !transmits(Some(block), models))
+            .map_or(SkyMap::OPEN, |height| height as i64);
+        SkyMap::new(|_, _| top)
+    }
+}
+
+pub struct VoidGenerator;
+
+impl WorldGenerator for VoidGenerator {
+    fn generate_chunk(&self, _pos: ChunkPos, _models: &BlockModels) -> ChunkData {
+        ChunkData::default()
+    }
+
+    fn generate_sky_map(&self, _pos: ChunkPos, _models: &BlockModels) -> SkyMap {
+        SkyMap::new(|_, _| SkyMap::OPEN)
+    }
+}
+
+/// Fills every other chunk below height 64, each with one of a few blocks so neighboring solid
+/// chunks can be told apart.
+pub struct CheckerboardGenerator;
+
+impl CheckerboardGenerator {
+    const BLOCKS: [Block; 4] = [Block::Rock, Block::Dirt, Block::Sand, Block::Glass];
+    /// The first chunk height that's left empty.
+    const TOP: i32 = 2;
+
+    /// The block filling the chunk, if it's one of the solid ones.
+    fn block(pos: ChunkPos) -> Option<Block> {
+        if pos.y >= Self::TOP || (pos.x + pos.y + pos.z).rem_euclid(2) != 0 {
+            return None;
+        }
+
+        let hash = BlockPos::new(pos.x as i64, pos.y as i64, pos.z as i64).hash_u32();
+        Some(Self::BLOCKS[hash as usize % Self::BLOCKS.len()])
+    }
+}
+
+impl WorldGenerator for CheckerboardGenerator {
+    fn generate_chunk(&self, pos: ChunkPos, _models: &BlockModels) -> ChunkData {
+        let mut chunk = ChunkData::default();
+        let Some(block) = Self::block(pos) else {
+            return chunk;
+        };
+
+        for (x, y, z) in iter_blocks() {
+            *chunk.block_mut(ChunkIndex::new(x, y, z)) = Some(block);
+        }
+        chunk
+    }
+
+    fn generate_sky_map(&self, pos: ChunkPos, models: &BlockModels) -> SkyMap {
+        // Glass lets the sun through, so the column goes on down to the first other block. Runs of
+        // glass chunks are short, so the search gives up long before any column would run out.
+        let top = (Self::TOP - 64..Self::TOP)
+            .rev()
+            .map(|y| ChunkPos::new(pos.x, y, pos.z))
+            .find(|&pos| !transmits(Self::block(pos), models))
+            .map_or(SkyMap::OPEN, |pos| {
+                pos.block_pos().y + CHUNK_SIZE as i64 - 1
+            });
+        SkyMap::new(|_, _| top)
+    }
+}